@@ -1,90 +1,585 @@
+use num_bigint::{BigUint, RandBigInt};
+use num_traits::{One, Zero};
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::env;
 use std::error::Error;
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 
-fn find_legal_skips(nums: &[u32]) -> u64 {
-    let mut total_paths = 1;
-    // The complete list is a legal path.
-    // 1 2 3 4 5
-    // If 2 is a legal skip, then 1 3 4 5 is a legal path, and we need to find its legal skips:
-    // 1 3 5 => done
-    // Then if 3 is a legal skip, 1 2 4 5 is a legal path. What are its skips?
-    // 1 2 5 => done
-
-    if nums.len() > 0 {
-        for (i, n) in nums.iter().skip(1).enumerate() {
-            // Is the nth number a legal skip?
-            if i + 2 < nums.len() && nums[i + 2] - nums[i] <= 3 {
-                total_paths += find_legal_skips(&nums[i + 2..]);
+// The rises allowed between consecutive links: either every rise in a
+// range or only those in a set, such as {1, 3}.
+#[derive(Clone, Debug, PartialEq)]
+enum Gaps {
+    Range(RangeInclusive<u32>),
+    Set(BTreeSet<u32>),
+}
+
+impl Gaps {
+    fn contains(&self, gap: u32) -> bool {
+        match self {
+            Gaps::Range(range) => range.contains(&gap),
+            Gaps::Set(set) => set.contains(&gap),
+        }
+    }
+
+    fn max(&self) -> u32 {
+        match self {
+            Gaps::Range(range) => *range.end(),
+            Gaps::Set(set) => set.iter().next_back().copied().unwrap_or(0),
+        }
+    }
+}
+
+// Parses a comma-separated set of gaps, e.g. "1,3".
+impl FromStr for Gaps {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Gaps, Box<dyn Error>> {
+        let set = s
+            .split(',')
+            .map(|gap| {
+                gap.trim()
+                    .parse()
+                    .map_err(|e| format!("Invalid gap {:?}: {}", gap, e))
+            })
+            .collect::<Result<BTreeSet<u32>, String>>()?;
+        Ok(Gaps::Set(set))
+    }
+}
+
+// The puzzle's gaps.
+const DEFAULT_GAPS: RangeInclusive<u32> = 1..=3;
+
+// How adapters may be chained: the outlet's rating, how far above the
+// highest adapter the device sits, and the allowed rise between
+// consecutive links.
+struct JoltageRules {
+    outlet: u32,
+    device_offset: u32,
+    gaps: Gaps,
+}
+
+impl Default for JoltageRules {
+    fn default() -> JoltageRules {
+        JoltageRules {
+            outlet: 0,
+            device_offset: 3,
+            gaps: Gaps::Range(DEFAULT_GAPS),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct InvalidGap {
+    from: u32,
+    to: u32,
+    gap: i64,
+}
+
+#[derive(Debug)]
+struct Validation {
+    // The outlet, every adapter in ascending order, then the device.
+    chain: Vec<u32>,
+    histogram: BTreeMap<i64, usize>,
+    invalid: Vec<InvalidGap>,
+}
+
+impl JoltageRules {
+    // Checks the chain using every adapter, collecting every gap rather
+    // than stopping at the first bad one. Adapters rated below the outlet
+    // show up as negative gaps. Fails only if the device's rating doesn't
+    // fit in a `u32`.
+    fn validate(&self, adapters: &[u32]) -> Result<Validation, Box<dyn Error>> {
+        let mut sorted = adapters.to_vec();
+        sorted.sort_unstable();
+
+        let highest = sorted.last().copied().unwrap_or(self.outlet);
+        let mut chain = vec![self.outlet];
+        chain.extend(sorted);
+        chain.push(highest.checked_add(self.device_offset).ok_or(format!(
+            "Device rating {} + {} is too high",
+            highest, self.device_offset
+        ))?);
+
+        let mut histogram = BTreeMap::new();
+        let mut invalid = Vec::new();
+        for pair in chain.windows(2) {
+            let gap = pair[1] as i64 - pair[0] as i64;
+            *histogram.entry(gap).or_insert(0) += 1;
+            if gap < 0 || !self.gaps.contains(gap as u32) {
+                invalid.push(InvalidGap {
+                    from: pair[0],
+                    to: pair[1],
+                    gap,
+                });
             }
         }
+
+        Ok(Validation {
+            chain,
+            histogram,
+            invalid,
+        })
+    }
+}
+
+// The arrangements of a sorted chain (including both ends), where each
+// step may skip adapters as long as it rises by an allowed gap.
+//
+// `counts[i]` is the number of ways to finish the chain from adapter `i`:
+// the sum over the adapters an allowed gap above it. For a range of gaps,
+// those form a sliding window over the sorted `chain` whose sum is kept as
+// it moves; for a set, each gap's adapters are looked up by rating. Either
+// way, building the counts is linear. They also rank arrangements in
+// lexicographic order, which gives indexing and uniform sampling.
+struct Arrangements<'a> {
+    chain: &'a [u32],
+    gaps: Gaps,
+    counts: Vec<BigUint>,
+}
+
+fn range_counts(chain: &[u32], gaps: &RangeInclusive<u32>) -> Vec<BigUint> {
+    let n = chain.len();
+    let mut counts = vec![BigUint::zero(); n];
+    let mut window = BigUint::zero();
+    // The window holds counts[lo..hi].
+    let (mut lo, mut hi) = (n, n);
+
+    for i in (0..n).rev() {
+        while lo > i + 1 && chain[lo - 1] - chain[i] >= *gaps.start() {
+            lo -= 1;
+            window += &counts[lo];
+        }
+        while hi > lo && chain[hi - 1] - chain[i] > *gaps.end() {
+            hi -= 1;
+            window -= &counts[hi];
+        }
+
+        counts[i] = if i == n - 1 {
+            BigUint::one()
+        } else {
+            window.clone()
+        };
     }
+    counts
+}
+
+fn set_counts(chain: &[u32], gaps: &BTreeSet<u32>) -> Vec<BigUint> {
+    let n = chain.len();
+    let mut counts = vec![BigUint::zero(); n];
+    // The total count of the adapters after `i` with each rating.
+    let mut by_rating: HashMap<u32, BigUint> = HashMap::new();
 
-    total_paths
+    for i in (0..n).rev() {
+        counts[i] = if i == n - 1 {
+            BigUint::one()
+        } else {
+            gaps.iter()
+                .filter_map(|&gap| by_rating.get(&chain[i].checked_add(gap)?))
+                .sum()
+        };
+        *by_rating.entry(chain[i]).or_default() += &counts[i];
+    }
+    counts
 }
 
-fn legal_paths(nums: &[u32], val: u32) -> u64 {
-    if nums.len() == 0 {
-        return 1;
+impl<'a> Arrangements<'a> {
+    fn new(chain: &'a [u32], gaps: Gaps) -> Arrangements<'a> {
+        let counts = match &gaps {
+            Gaps::Range(range) => range_counts(chain, range),
+            Gaps::Set(set) => set_counts(chain, set),
+        };
+
+        Arrangements {
+            chain,
+            gaps,
+            counts,
+        }
     }
 
-    let mut total_paths = 0;
+    fn count(&self) -> BigUint {
+        self.counts.first().cloned().unwrap_or_else(BigUint::zero)
+    }
 
-    if nums.len() >= 1 && nums[0] - val <= 3 {
-        // First out is a valid choice
-        total_paths += legal_paths(&nums[1..], nums[0])
+    // Adapters an allowed gap above `i` from which the chain can be
+    // finished, in ascending order.
+    fn next_steps(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let from = self.chain[i];
+        (i + 1..self.chain.len())
+            .take_while(move |&j| self.chain[j] - from <= self.gaps.max())
+            .filter(move |&j| self.gaps.contains(self.chain[j] - from) && !self.counts[j].is_zero())
     }
-    if nums.len() >= 2 && nums[1] - val <= 3 {
-        // Second out is a valid choice
-        total_paths += legal_paths(&nums[2..], nums[1])
+
+    // The arrangement at position `k` in lexicographic order.
+    fn kth(&self, k: &BigUint) -> Option<Vec<u32>> {
+        if *k >= self.count() {
+            return None;
+        }
+
+        let mut k = k.clone();
+        let mut i = 0;
+        let mut arrangement = vec![self.chain[0]];
+        while i < self.chain.len() - 1 {
+            for j in self.next_steps(i) {
+                if k < self.counts[j] {
+                    i = j;
+                    break;
+                }
+                k -= &self.counts[j];
+            }
+            arrangement.push(self.chain[i]);
+        }
+        Some(arrangement)
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Option<Vec<u32>> {
+        let count = self.count();
+        if count.is_zero() {
+            return None;
+        }
+        self.kth(&rng.gen_biguint_below(&count))
     }
-    if nums.len() >= 3 && nums[2] - val <= 3 {
-        // Third out is a valid choice
-        total_paths += legal_paths(&nums[3..], nums[2])
+
+    fn iter(&self) -> ArrangementIter<'_, 'a> {
+        ArrangementIter {
+            arrangements: self,
+            stack: if self.count().is_zero() {
+                Vec::new()
+            } else {
+                vec![(0, 1)]
+            },
+        }
     }
+}
 
-    return total_paths;
+// Lazily walks the arrangements in lexicographic order. Each stack entry is
+// an adapter on the current path and the next candidate to try after it.
+// Dead ends are never entered, so each arrangement costs only its length.
+struct ArrangementIter<'b, 'a> {
+    arrangements: &'b Arrangements<'a>,
+    stack: Vec<(usize, usize)>,
 }
 
+impl Iterator for ArrangementIter<'_, '_> {
+    type Item = Vec<u32>;
+
+    fn next(&mut self) -> Option<Vec<u32>> {
+        let a = self.arrangements;
+        let last = a.chain.len() - 1;
+
+        loop {
+            let &(i, from) = self.stack.last()?;
+            if i == last {
+                let arrangement = self.stack.iter().map(|&(i, _)| a.chain[i]).collect();
+                self.stack.pop();
+                return Some(arrangement);
+            }
+
+            match a.next_steps(i).find(|&j| j >= from) {
+                Some(j) => {
+                    self.stack.last_mut().unwrap().1 = j + 1;
+                    self.stack.push((j, j + 1));
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+fn read_adapters(file_name: &str) -> Result<Vec<u32>, Box<dyn Error>> {
+    let file = BufReader::new(File::open(file_name)?);
+
+    file.lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line?;
+            line.trim()
+                .parse()
+                .map_err(|e| format!("line {}: cannot parse {:?}: {}", i + 1, line, e).into())
+        })
+        .collect()
+}
+
+fn parse_arg<T: FromStr>(arg: Option<String>) -> Result<T, Box<dyn Error>>
+where
+    T::Err: Error + 'static,
+{
+    Ok(arg.ok_or("Missing argument")?.parse()?)
+}
+
+// Usage: day10 [--list N] [--sample] [--kth K] [--gaps G1,G2,...] [OUTLET]
+//              [DEVICE_OFFSET] [MIN_GAP] [MAX_GAP]
+// `--gaps` allows exactly the listed gaps, in place of MIN_GAP..=MAX_GAP.
 fn main() -> Result<(), Box<dyn Error>> {
-    let file = BufReader::new(File::open("input.txt")?);
-
-    let mut numbers = file
-        .lines()
-        .map(|x| x.unwrap().parse().unwrap())
-        .collect::<Vec<u32>>();
-
-    numbers.push(0);
-    numbers.sort();
-    numbers.push(numbers[numbers.len() - 1] + 3);
-
-    let diffs_counts = numbers
-        .iter()
-        .zip(numbers.iter().skip(1))
-        .map(|(a, b)| b - a)
-        .fold((0, 0, 0), |(ones, twos, threes), x| match x {
-            1 => (ones + 1, twos, threes),
-            2 => (ones, twos + 1, threes),
-            3 => (ones, twos, threes + 1),
-            _ => panic!("Bad differential {}", x),
-        });
-
-    println!("Diffs: {:?}", diffs_counts);
-    println!("Output: {}", diffs_counts.0 * diffs_counts.2);
-
-    let skippables: u32 = (1..numbers.len() - 1).fold(0, |acc, x| {
-        if numbers[x + 1] - numbers[x - 1] <= 3 {
-            acc + 1
-        } else {
-            acc
-        }
-    });
-    println!("Skippables: {}", skippables);
-    // Maybe try: for each Skippable, check which other skippables are still skippable...? But are there situations where two skippables being skipped make a third non-skippable?
-    // (0) 1 2 3 4 5
-    // 3 is _per se_ skippable but is not skippable if 1 and 2 are both skipped
-//    println!("find_legal_skips: {}", find_legal_skips(&numbers[..]));
-    println!("find_legal_paths: {}", legal_paths(&numbers[1..], 0));
+    let mut list = None;
+    let mut sample = false;
+    let mut kth = None;
+    let mut gaps = None;
+    let mut args = Vec::new();
+
+    let mut argv = env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--list" => list = Some(parse_arg::<usize>(argv.next())?),
+            "--sample" => sample = true,
+            "--kth" => kth = Some(parse_arg::<BigUint>(argv.next())?),
+            "--gaps" => gaps = Some(argv.next().ok_or("Missing argument")?.parse::<Gaps>()?),
+            _ => args.push(arg.parse::<u32>()?),
+        }
+    }
+
+    if gaps.is_some() && args.len() > 2 {
+        return Err("Give either --gaps or MIN_GAP and MAX_GAP, not both".into());
+    }
+    let defaults = JoltageRules::default();
+    let rules = JoltageRules {
+        outlet: args.first().copied().unwrap_or(defaults.outlet),
+        device_offset: args.get(1).copied().unwrap_or(defaults.device_offset),
+        gaps: gaps.unwrap_or_else(|| {
+            Gaps::Range(
+                args.get(2).copied().unwrap_or(*DEFAULT_GAPS.start())
+                    ..=args.get(3).copied().unwrap_or(*DEFAULT_GAPS.end()),
+            )
+        }),
+    };
+
+    let numbers = read_adapters("input.txt")?;
+    let validation = rules.validate(&numbers)?;
+    println!("Diffs: {:?}", validation.histogram);
+    println!(
+        "Output: {}",
+        validation.histogram.get(&1).unwrap_or(&0) * validation.histogram.get(&3).unwrap_or(&0)
+    );
+
+    for bad in &validation.invalid {
+        println!(
+            "Invalid gap of {} between {} and {}",
+            bad.gap, bad.from, bad.to
+        );
+    }
+
+    // Skipping adapters can still avoid disallowed gaps, but not adapters
+    // rated below the outlet.
+    if validation.invalid.iter().all(|bad| bad.gap >= 0) {
+        let arrangements = Arrangements::new(&validation.chain, rules.gaps.clone());
+        println!("Arrangements: {}", arrangements.count());
+
+        if let Some(n) = list {
+            for arrangement in arrangements.iter().take(n) {
+                println!("{:?}", arrangement);
+            }
+        }
+        if sample {
+            if let Some(arrangement) = arrangements.sample(&mut rand::thread_rng()) {
+                println!("Sample: {:?}", arrangement);
+            }
+        }
+        if let Some(k) = kth {
+            match arrangements.kth(&k) {
+                Some(arrangement) => println!("Arrangement {}: {:?}", k, arrangement),
+                None => println!("There is no arrangement {}", k),
+            }
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: [u32; 11] = [16, 10, 15, 5, 1, 11, 7, 19, 6, 12, 4];
+    const LARGE: [u32; 31] = [
+        28, 33, 18, 42, 31, 14, 46, 20, 48, 47, 24, 23, 49, 45, 19, 38, 39, 11, 1, 32, 25, 35, 8,
+        17, 7, 9, 4, 2, 34, 10, 3,
+    ];
+
+    #[test]
+    fn test_validate() {
+        let rules = JoltageRules::default();
+
+        let validation = rules.validate(&SMALL).unwrap();
+        assert_eq!(
+            vec![(1, 7), (3, 5)],
+            validation.histogram.into_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(Vec::<InvalidGap>::new(), validation.invalid);
+
+        let validation = rules.validate(&LARGE).unwrap();
+        assert_eq!(
+            vec![(1, 22), (3, 10)],
+            validation.histogram.into_iter().collect::<Vec<_>>()
+        );
+
+        let validation = rules.validate(&[1, 6]).unwrap();
+        assert_eq!(
+            vec![InvalidGap {
+                from: 1,
+                to: 6,
+                gap: 5
+            }],
+            validation.invalid
+        );
+    }
+
+    #[test]
+    fn test_count() {
+        let rules = JoltageRules::default();
+
+        let chain = rules.validate(&SMALL).unwrap().chain;
+        assert_eq!(
+            BigUint::from(8u32),
+            Arrangements::new(&chain, rules.gaps.clone()).count()
+        );
+
+        let chain = rules.validate(&LARGE).unwrap().chain;
+        assert_eq!(
+            BigUint::from(19208u32),
+            Arrangements::new(&chain, rules.gaps.clone()).count()
+        );
+    }
+
+    #[test]
+    fn test_iter_and_kth_agree() {
+        let rules = JoltageRules::default();
+
+        for adapters in [&SMALL[..], &LARGE[..]] {
+            let chain = rules.validate(adapters).unwrap().chain;
+            let arrangements = Arrangements::new(&chain, rules.gaps.clone());
+            let all = arrangements.iter().collect::<Vec<Vec<u32>>>();
+
+            assert_eq!(BigUint::from(all.len()), arrangements.count());
+            for (k, arrangement) in all.iter().enumerate() {
+                assert_eq!(
+                    Some(arrangement),
+                    arrangements.kth(&BigUint::from(k)).as_ref()
+                );
+            }
+            assert_eq!(None, arrangements.kth(&arrangements.count()));
+
+            // Lexicographic order, with no repeats.
+            assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+
+        let chain = rules.validate(&SMALL).unwrap().chain;
+        let arrangements = Arrangements::new(&chain, rules.gaps.clone());
+        assert_eq!(
+            Some(vec![0, 1, 4, 5, 6, 7, 10, 11, 12, 15, 16, 19, 22]),
+            arrangements.kth(&BigUint::zero())
+        );
+        assert_eq!(
+            Some(vec![0, 1, 4, 7, 10, 12, 15, 16, 19, 22]),
+            arrangements.kth(&BigUint::from(7u32))
+        );
+    }
+
+    #[test]
+    fn test_gap_sets() {
+        let rules = JoltageRules {
+            gaps: "1, 3".parse().unwrap(),
+            ..JoltageRules::default()
+        };
+        assert_eq!(Gaps::Set([1, 3].iter().copied().collect()), rules.gaps);
+        assert!("1,x".parse::<Gaps>().is_err());
+        assert!("".parse::<Gaps>().is_err());
+
+        let validation = rules.validate(&[1, 2, 4, 5, 7]).unwrap();
+        assert_eq!(
+            vec![
+                InvalidGap {
+                    from: 2,
+                    to: 4,
+                    gap: 2
+                },
+                InvalidGap {
+                    from: 5,
+                    to: 7,
+                    gap: 2
+                }
+            ],
+            validation.invalid
+        );
+        let arrangements = Arrangements::new(&validation.chain, rules.gaps.clone());
+        assert_eq!(BigUint::one(), arrangements.count());
+        assert_eq!(
+            vec![vec![0, 1, 4, 7, 10]],
+            arrangements.iter().collect::<Vec<Vec<u32>>>()
+        );
+
+        // A set with no holes counts the same as the range.
+        let range = JoltageRules::default();
+        let set = JoltageRules {
+            gaps: "1,2,3".parse().unwrap(),
+            ..JoltageRules::default()
+        };
+        for adapters in [&SMALL[..], &LARGE[..]] {
+            let chain = range.validate(adapters).unwrap().chain;
+            assert_eq!(
+                Arrangements::new(&chain, range.gaps.clone()).count(),
+                Arrangements::new(&chain, set.gaps.clone()).count()
+            );
+        }
+
+        // Without 2, the large example's arrangements are those counted
+        // by iterating.
+        let chain = rules.validate(&LARGE).unwrap().chain;
+        let arrangements = Arrangements::new(&chain, rules.gaps.clone());
+        assert_eq!(
+            BigUint::from(arrangements.iter().count()),
+            arrangements.count()
+        );
+    }
+
+    #[test]
+    fn test_no_arrangements() {
+        let rules = JoltageRules::default();
+        let chain = rules.validate(&[1, 6]).unwrap().chain;
+        let arrangements = Arrangements::new(&chain, rules.gaps.clone());
+
+        assert!(arrangements.count().is_zero());
+        assert_eq!(None, arrangements.iter().next());
+        assert_eq!(None, arrangements.kth(&BigUint::zero()));
+        assert_eq!(None, arrangements.sample(&mut rand::thread_rng()));
+    }
+
+    #[test]
+    fn test_device_overflow() {
+        let rules = JoltageRules::default();
+
+        assert_eq!(
+            u32::MAX,
+            *rules
+                .validate(&[u32::MAX - 3])
+                .unwrap()
+                .chain
+                .last()
+                .unwrap()
+        );
+        assert_eq!(
+            "Device rating 4294967293 + 3 is too high",
+            rules.validate(&[u32::MAX - 2]).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_read_adapters() {
+        let path = env::temp_dir().join(format!("day10_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "16\n 10 \n-1\n").unwrap();
+        let file_name = path.to_str().unwrap();
+
+        assert_eq!(
+            "line 3: cannot parse \"-1\": invalid digit found in string",
+            read_adapters(file_name).unwrap_err().to_string()
+        );
+        std::fs::write(&path, "16\n 10 \n").unwrap();
+        assert_eq!(vec![16, 10], read_adapters(file_name).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
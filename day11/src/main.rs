@@ -1,7 +1,21 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Read};
+use std::ops::Range;
+use std::thread;
+use std::time::Duration;
+
+mod render;
+mod stats;
+
+use render::{AnsiPlayer, Exporter, GifWriter, PpmWriter};
+use stats::Stats;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 struct Pair {
     x: usize,
@@ -14,116 +28,401 @@ struct Delta {
     y: i32,
 }
 
+const DIRECTIONS: [Delta; 8] = [
+    Delta { x: -1, y: -1 },
+    Delta { x: 0, y: -1 },
+    Delta { x: 1, y: -1 },
+    Delta { x: -1, y: 0 },
+    Delta { x: 1, y: 0 },
+    Delta { x: -1, y: 1 },
+    Delta { x: 0, y: 1 },
+    Delta { x: 1, y: 1 },
+];
+
+// An outer-totalistic cellular automaton: each cell's next state depends on
+// its own state and how many of its neighbours are live. Automata are
+// shared between threads when generations are stepped in parallel.
+trait Automaton: Clone + Sync {
+    // The bytes a cell may hold.
+    fn alphabet(&self) -> &[u8];
+
+    // Passes each cell whose state feeds into `cell`'s next state to
+    // `visit`.
+    fn neighbours<F: FnMut(Pair)>(&self, sm: &StateMachine<Self>, cell: Pair, visit: F);
+
+    fn is_live(&self, cell: u8) -> bool;
+
+    fn transition(&self, cell: u8, live_neighbours: usize) -> u8;
+
+    // Checks the automaton's own configuration before it is used.
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
+}
+
+// Seating rules: an empty seat (`L`) is taken when no seat it watches is
+// occupied (`#`), and an occupied seat is left once `threshold` of them are.
+// A seat watches along each of the eight directions, up to `radius` cells
+// away or without limit, until it reaches a cell in `blocking`. With `wrap`
+// the grid is a torus, and a seat may see the same seat from more than one
+// direction.
+#[derive(Clone, Debug, PartialEq)]
+struct SeatingRules {
+    threshold: usize,
+    radius: Option<usize>,
+    blocking: Vec<u8>,
+    wrap: bool,
+}
+
+impl Automaton for SeatingRules {
+    fn alphabet(&self) -> &[u8] {
+        b"L#."
+    }
+
+    fn neighbours<F: FnMut(Pair)>(&self, sm: &StateMachine<Self>, cell: Pair, visit: F) {
+        sm.get_visible_seats(cell, self.radius, &self.blocking, self.wrap, visit)
+    }
+
+    fn is_live(&self, cell: u8) -> bool {
+        cell == b'#'
+    }
+
+    fn transition(&self, cell: u8, live_neighbours: usize) -> u8 {
+        seat_transition(cell, live_neighbours, self.threshold)
+    }
+
+    // Neighbourhoods are worked out once, up front, so they must not change
+    // as seats fill and empty.
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(&c) = self.blocking.iter().find(|c| !self.alphabet().contains(c)) {
+            return Err(format!("invalid blocking cell {:?}", c as char).into());
+        }
+        if self.blocking.contains(&b'L') != self.blocking.contains(&b'#') {
+            return Err("empty and occupied seats must both block sight or neither".into());
+        }
+        Ok(())
+    }
+}
+
+fn seat_transition(cell: u8, live_neighbours: usize, threshold: usize) -> u8 {
+    if cell == b'L' && live_neighbours == 0 {
+        // This seat becomes occupied
+        b'#'
+    } else if cell == b'#' && live_neighbours >= threshold {
+        // This seat becomes empty
+        b'L'
+    } else {
+        cell
+    }
+}
+
+// The puzzle's seating rules. Seats watch either their eight adjacent cells
+// or the first seat in each of the eight directions, looking across floor
+// (`.`).
 #[derive(Clone, Debug, PartialEq)]
 enum Mode {
     Adjacency,
     Visibility,
 }
 
+// Both modes stop looking at the first seat, empty or occupied.
+const SEATS: &[u8] = b"L#";
+
+impl Mode {
+    fn threshold(&self) -> usize {
+        match self {
+            Mode::Adjacency => 4,
+            Mode::Visibility => 5,
+        }
+    }
+
+    fn radius(&self) -> Option<usize> {
+        match self {
+            Mode::Adjacency => Some(1),
+            Mode::Visibility => None,
+        }
+    }
+
+    fn rules(&self) -> SeatingRules {
+        SeatingRules {
+            threshold: self.threshold(),
+            radius: self.radius(),
+            blocking: SEATS.to_vec(),
+            wrap: false,
+        }
+    }
+}
+
+impl Automaton for Mode {
+    fn alphabet(&self) -> &[u8] {
+        b"L#."
+    }
+
+    fn neighbours<F: FnMut(Pair)>(&self, sm: &StateMachine<Self>, cell: Pair, visit: F) {
+        sm.get_visible_seats(cell, self.radius(), SEATS, false, visit)
+    }
+
+    fn is_live(&self, cell: u8) -> bool {
+        cell == b'#'
+    }
+
+    fn transition(&self, cell: u8, live_neighbours: usize) -> u8 {
+        seat_transition(cell, live_neighbours, self.threshold())
+    }
+}
+
+// Conway's Game of Life (B3/S23): `#` is alive, `.` dead.
+#[derive(Clone, Debug, PartialEq)]
+struct Life;
+
+impl Automaton for Life {
+    fn alphabet(&self) -> &[u8] {
+        b"#."
+    }
+
+    fn neighbours<F: FnMut(Pair)>(&self, sm: &StateMachine<Self>, cell: Pair, visit: F) {
+        sm.get_adjacent(cell, visit)
+    }
+
+    fn is_live(&self, cell: u8) -> bool {
+        cell == b'#'
+    }
+
+    fn transition(&self, cell: u8, live_neighbours: usize) -> u8 {
+        match (cell, live_neighbours) {
+            (b'#', 2) | (_, 3) => b'#',
+            _ => b'.',
+        }
+    }
+}
+
+// Brian's Brain: off cells (`.`) with exactly two firing neighbours fire
+// (`#`), firing cells start dying (`o`), and dying cells switch off.
 #[derive(Clone, Debug, PartialEq)]
-struct StateMachine {
+struct BriansBrain;
+
+impl Automaton for BriansBrain {
+    fn alphabet(&self) -> &[u8] {
+        b"#o."
+    }
+
+    fn neighbours<F: FnMut(Pair)>(&self, sm: &StateMachine<Self>, cell: Pair, visit: F) {
+        sm.get_adjacent(cell, visit)
+    }
+
+    fn is_live(&self, cell: u8) -> bool {
+        cell == b'#'
+    }
+
+    fn transition(&self, cell: u8, live_neighbours: usize) -> u8 {
+        match (cell, live_neighbours) {
+            (b'#', _) => b'o',
+            (b'o', _) => b'.',
+            (_, 2) => b'#',
+            _ => b'.',
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct StateMachine<A> {
     state: Vec<u8>,
     size: Pair,
-    mode: Mode,
+    mode: A,
 }
 
+// Walks from a cell in a straight line, either stopping at the edge of the
+// grid or, when wrapping, continuing across it until back at the start.
 struct VectorIterator {
     size: Pair,
+    start: Pair,
     cur: Pair,
     vector: Delta,
+    wrap: bool,
 }
 
 impl Iterator for VectorIterator {
     type Item = Pair;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next_x = (self.cur.x as i32) + self.vector.x;
-        let next_y = (self.cur.y as i32) + self.vector.y;
+        let mut next_x = (self.cur.x as i32) + self.vector.x;
+        let mut next_y = (self.cur.y as i32) + self.vector.y;
+
+        if self.wrap {
+            next_x = next_x.rem_euclid(self.size.x as i32);
+            next_y = next_y.rem_euclid(self.size.y as i32);
+        }
 
         if next_x < 0
             || next_x as usize >= self.size.x
             || next_y < 0
             || next_y as usize >= self.size.y
         {
+            return None;
+        }
+
+        self.cur = Pair {
+            x: next_x as usize,
+            y: next_y as usize,
+        };
+        if self.cur == self.start {
             None
         } else {
-            self.cur = Pair {
-                x: next_x as usize,
-                y: next_y as usize,
-            };
             Some(self.cur)
         }
     }
 }
 
-impl fmt::Display for StateMachine {
+impl<A> fmt::Display for StateMachine<A> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Size: ({}, {})\n", self.size.x, self.size.y)?;
-        for row in 0..self.size.y {
-            write!(
-                f,
-                "{}",
-                std::str::from_utf8(
-                    &self.state[((self.size.x + 1) * row)..((self.size.x + 1) * (row + 1))]
-                )
-                .unwrap()
-            )?;
+        writeln!(f, "Size: ({}, {})", self.size.x, self.size.y)?;
+        for row in self.state.chunks(self.size.x) {
+            writeln!(f, "{}", String::from_utf8_lossy(row))?;
         }
         Ok(())
     }
 }
 
-impl StateMachine {
-    fn from_vec(content: Vec<u8>, mode: Mode) -> Result<StateMachine, Box<dyn Error>> {
-        let x = content
-            .iter()
-            .position(|&e| e == b'\n')
-            .ok_or("invalid format")?;
-        let y = content.len() / (x + 1);
+impl<A: Automaton> StateMachine<A> {
+    // Reads one row per line, with or without a final line break. Lines
+    // may end in `\r\n`, and every row must be the same width.
+    fn from_vec(content: Vec<u8>, mode: A) -> Result<StateMachine<A>, Box<dyn Error>> {
+        mode.validate()?;
+
+        let mut rows = content
+            .split(|&c| c == b'\n')
+            .map(|row| row.strip_suffix(b"\r").unwrap_or(row))
+            .collect::<Vec<&[u8]>>();
+        if rows.last() == Some(&&b""[..]) {
+            rows.pop();
+        }
+
+        let x = rows.first().map_or(0, |row| row.len());
+        if x == 0 {
+            return Err("invalid format: empty layout".into());
+        }
+        if x.saturating_mul(rows.len()) > u32::MAX as usize {
+            return Err(format!("invalid format: {}x{} is too many cells", x, rows.len()).into());
+        }
+        let mut state = Vec::with_capacity(x * rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            if row.len() != x {
+                return Err(format!(
+                    "invalid format: row {} has {} cells, expected {}",
+                    y + 1,
+                    row.len(),
+                    x
+                )
+                .into());
+            }
+            if let Some(&c) = row.iter().find(|c| !mode.alphabet().contains(c)) {
+                return Err(format!("invalid cell {:?} in row {}", c as char, y + 1).into());
+            }
+            state.extend_from_slice(row);
+        }
 
         Ok(StateMachine {
-            state: content,
-            size: Pair { x, y },
+            state,
+            size: Pair { x, y: rows.len() },
             mode,
         })
     }
-    fn from_file(file_name: &str, mode: Mode) -> Result<StateMachine, Box<dyn Error>> {
+
+    fn from_file(file_name: &str, mode: A) -> Result<StateMachine<A>, Box<dyn Error>> {
         let mut file = File::open(file_name)?;
-        let mut content = Vec::new();
+        let mut content = String::new();
 
-        file.read_to_end(&mut content)?;
-        StateMachine::from_vec(content, mode)
+        file.read_to_string(&mut content)?;
+        StateMachine::from_string(&content, mode)
     }
 
-    fn from_string(content: &str, mode: Mode) -> Result<StateMachine, Box<dyn Error>> {
+    fn from_string(content: &str, mode: A) -> Result<StateMachine<A>, Box<dyn Error>> {
         StateMachine::from_vec(content.as_bytes().to_vec(), mode)
     }
 
+    // Every cell in row-major order.
+    fn cells(&self) -> &[u8] {
+        &self.state
+    }
+
     pub fn get_occupied_seats(&self) -> usize {
-        (0..self.state.len())
-            .filter(|&x| *self.state.get(x).unwrap() == b'#')
-            .count()
+        self.state.iter().filter(|&&c| self.mode.is_live(c)).count()
     }
 
-    pub fn set_cell(&mut self, cell: Pair, value: u8) {
-        if cell.x < self.size.x && cell.y < self.size.y {
-            let pos = cell.y * (self.size.x + 1) + cell.x;
-            self.state[pos] = value;
-        }
+    // Where `cell` lives in `state`.
+    fn position(&self, cell: Pair) -> usize {
+        cell.y * self.size.x + cell.x
     }
 
     pub fn get_cell(&self, cell: Pair) -> Option<&u8> {
         if cell.x < self.size.x && cell.y < self.size.y {
-            let pos = cell.y * (self.size.x + 1) + cell.x;
-            self.state.get(pos)
+            self.state.get(self.position(cell))
         } else {
             None
         }
     }
 
+    // Visits the eight cells around `cell` that lie inside the grid.
+    fn get_adjacent<F: FnMut(Pair)>(&self, cell: Pair, visit: F) {
+        DIRECTIONS
+            .iter()
+            .filter_map(|&vector| self.get_vector_iterator(cell, vector, false).next())
+            .for_each(visit)
+    }
+
+    // Visits the seats `cell` watches: in each direction, every seat up to
+    // `radius` cells away and up to and including the first cell in
+    // `blocking`.
+    fn get_visible_seats<F: FnMut(Pair)>(
+        &self,
+        cell: Pair,
+        radius: Option<usize>,
+        blocking: &[u8],
+        wrap: bool,
+        mut visit: F,
+    ) {
+        for &vector in DIRECTIONS.iter() {
+            let line = self
+                .get_vector_iterator(cell, vector, wrap)
+                .take(radius.unwrap_or(usize::MAX));
+            for pair in line {
+                let c = *self.get_cell(pair).unwrap();
+                if c != b'.' {
+                    visit(pair);
+                }
+                if blocking.contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn get_vector_iterator(&self, cell: Pair, vector: Delta, wrap: bool) -> VectorIterator {
+        VectorIterator {
+            size: self.size,
+            start: cell,
+            cur: cell,
+            vector,
+            wrap,
+        }
+    }
+}
+
+// Single-cell access and stepping one generation at a time. The solver
+// runs a `Simulation` instead, so within the crate only the tests use
+// these.
+#[cfg_attr(not(test), allow(dead_code))]
+impl<A: Automaton> StateMachine<A> {
+    pub fn set_cell(&mut self, cell: Pair, value: u8) {
+        if cell.x < self.size.x && cell.y < self.size.y {
+            let pos = self.position(cell);
+            self.state[pos] = value;
+        }
+    }
+
     pub fn get_neighbor_value(&self, cell: Pair) -> u8 {
         if let Some(&x) = self.get_cell(cell) {
-            if x == b'#' {
+            if self.mode.is_live(x) {
                 1
             } else {
                 0
@@ -133,131 +432,553 @@ impl StateMachine {
         }
     }
 
-    pub fn get_neighbor_count(&self, cell: Pair) -> u8 {
+    pub fn get_neighbor_count(&self, cell: Pair) -> usize {
         let mut count = 0;
-        let x = cell.x as i32;
-        let y = cell.y as i32;
-
-        for &x in [x - 1, x, x + 1].iter() {
-            for &y in [y - 1, y, y + 1].iter() {
-                let pair = Pair {
-                    x: x as usize,
-                    y: y as usize,
-                };
-                if pair == cell {
-                    continue;
-                }
-                count += self.get_neighbor_value(pair);
+        self.mode.neighbours(self, cell, |pair| {
+            count += self.get_neighbor_value(pair) as usize
+        });
+        count
+    }
+
+    pub fn next_state(&self) -> StateMachine<A> {
+        let mut simulation = Simulation::new(self.clone());
+        simulation.step();
+        simulation.into_state()
+    }
+}
+
+// Every cell's neighbours as positions in `state`, flattened: the
+// neighbours of the cell at position `i` are
+// `targets[offsets[i]..offsets[i + 1]]`. Positions are stored as `u32`,
+// which `StateMachine::from_vec` guarantees is wide enough, to halve the
+// size of the graph; the offsets stay `usize` as there may be more
+// neighbours than cells.
+// Neighbourhoods may depend on cells that the automaton never changes,
+// such as floor blocking sight, but not on any cell that it does.
+struct NeighbourGraph {
+    offsets: Vec<usize>,
+    targets: Vec<u32>,
+}
+
+impl NeighbourGraph {
+    fn new<A: Automaton>(sm: &StateMachine<A>) -> NeighbourGraph {
+        let mut offsets = Vec::with_capacity(sm.state.len() + 1);
+        let mut targets = Vec::new();
+
+        offsets.push(0);
+        for y in 0..sm.size.y {
+            for x in 0..sm.size.x {
+                sm.mode
+                    .neighbours(sm, Pair { x, y }, |n| targets.push(sm.position(n) as u32));
+                offsets.push(targets.len());
             }
         }
 
-        count
+        NeighbourGraph { offsets, targets }
     }
 
-    fn get_vector_iterator(&self, cell: Pair, vector: Delta) -> VectorIterator {
-        VectorIterator {
-            size: self.size,
-            cur: cell,
-            vector: vector,
-        }
+    fn neighbours(&self, i: usize) -> &[u32] {
+        &self.targets[self.offsets[i]..self.offsets[i + 1]]
     }
+}
 
-    pub fn get_visible_count(&self, cell: Pair) -> u8 {
-        let mut count = 0;
+// Steps a state machine over its precomputed neighbour graph, writing each
+// generation into a second buffer and swapping rather than cloning. With
+// more than one thread, the rows are split into contiguous bands that are
+// stepped in parallel; every band reads the whole previous generation and
+// writes only its own rows, so the result matches the serial version.
+struct Simulation<A> {
+    sm: StateMachine<A>,
+    next: Vec<u8>,
+    graph: NeighbourGraph,
+    threads: usize,
+    generation: usize,
+    // Hashes states for cycle detection.
+    hash: fn(&[u8]) -> u64,
+}
 
-        for &x_delta in [-1, 0, 1].iter() {
-            for &y_delta in [-1, 0, 1].iter() {
-                if x_delta == 0 && y_delta == 0 {
-                    continue;
-                }
+fn hash_state(state: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    hasher.finish()
+}
 
-                let first_seat = self
-                    .get_vector_iterator(
-                        cell,
-                        Delta {
-                            x: x_delta,
-                            y: y_delta,
-                        },
-                    )
-                    .filter(|&cell| *self.get_cell(cell).unwrap() != b'.')
-                    .next();
-
-                count +=
-                    if first_seat.is_some() && self.get_neighbor_value(first_seat.unwrap()) == 1 {
-                        1
-                    } else {
-                        0
-                    };
-            }
+// A generation whose state recurs every `period` generations. A fixed
+// point has a period of 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Cycle {
+    start: usize,
+    period: usize,
+}
+
+// How a capped run ended: after `generations` generations, either having
+// found a cycle or having hit the cap, in `state`.
+struct Run<A> {
+    generations: usize,
+    cycle: Option<Cycle>,
+    state: StateMachine<A>,
+}
+
+impl<A: Automaton> Simulation<A> {
+    fn new(sm: StateMachine<A>) -> Simulation<A> {
+        Simulation {
+            next: sm.state.clone(),
+            graph: NeighbourGraph::new(&sm),
+            sm,
+            threads: 1,
+            generation: 0,
+            hash: hash_state,
         }
+    }
 
-        count
+    fn with_threads(mut self, threads: usize) -> Simulation<A> {
+        self.threads = threads.max(1);
+        self
+    }
+
+    fn into_state(self) -> StateMachine<A> {
+        self.sm
+    }
+
+    // Computes the next state of the cells at `positions` into `out`.
+    // Returns whether any cell changed.
+    fn step_band(&self, positions: Range<usize>, out: &mut [u8]) -> bool {
+        let state = &self.sm.state;
+        let mode = &self.sm.mode;
+        let base = positions.start;
+        let mut changed = false;
+
+        for pos in positions {
+            let live = self
+                .graph
+                .neighbours(pos)
+                .iter()
+                .filter(|&&n| mode.is_live(state[n as usize]))
+                .count();
+            let cell = mode.transition(state[pos], live);
+
+            changed |= cell != state[pos];
+            out[pos - base] = cell;
+        }
+        changed
     }
 
-    pub fn next_state(&self) -> StateMachine {
-        let mut next = self.clone();
-        let threshold = if let Mode::Adjacency = self.mode {
-            4
+    // Advances one generation, returning whether any cell changed.
+    fn step(&mut self) -> bool {
+        let Pair {
+            x: width,
+            y: height,
+        } = self.sm.size;
+        let bands = self.threads.min(height).max(1);
+        let mut next = std::mem::take(&mut self.next);
+
+        let changed = if bands == 1 {
+            self.step_band(0..next.len(), &mut next)
         } else {
-            5
+            let band_len = height.div_ceil(bands) * width;
+            let this = &*self;
+
+            thread::scope(|scope| {
+                let handles = next
+                    .chunks_mut(band_len)
+                    .enumerate()
+                    .map(|(i, band)| {
+                        let start = i * band_len;
+                        scope.spawn(move || this.step_band(start..start + band.len(), band))
+                    })
+                    .collect::<Vec<_>>();
+
+                handles
+                    .into_iter()
+                    .fold(false, |changed, h| h.join().unwrap() | changed)
+            })
         };
 
-        for x in 0..self.size.x {
-            for y in 0..self.size.y {
-                let pair = Pair { x, y };
-                let cell = *self.get_cell(pair).unwrap();
-
-                let neighbor_value = if let Mode::Adjacency = self.mode {
-                    self.get_neighbor_count(pair)
-                } else {
-                    self.get_visible_count(pair)
-                };
-
-                if cell == b'L' && neighbor_value == 0 {
-                    // This seat becomes occupied
-                    next.set_cell(pair, b'#');
-                } else if cell == b'#' && neighbor_value >= threshold {
-                    // This seat becomes empty
-                    next.set_cell(pair, b'L');
+        self.next = std::mem::replace(&mut self.sm.state, next);
+        self.generation += 1;
+        changed
+    }
+
+    // The state at `generation`, found by stepping forward from `initial`,
+    // the state at `from`. The simulation is left as it was.
+    fn replay(&mut self, initial: &[u8], from: usize, generation: usize) -> Vec<u8> {
+        let current = std::mem::replace(&mut self.sm.state, initial.to_vec());
+        let current_generation = self.generation;
+
+        for _ in from..generation {
+            self.step();
+        }
+        self.generation = current_generation;
+        std::mem::replace(&mut self.sm.state, current)
+    }
+
+    // Steps until the state repeats or `max_generations` have passed in
+    // total, returning the cycle found, if any. Fixed points are spotted
+    // exactly; longer cycles by remembering a 64-bit hash of every state
+    // seen, so a cycle is reported at its first repeated state. Hashes can
+    // collide, so a match is only trusted once replaying from the starting
+    // state confirms it, which costs one extra pass up to the repeated state.
+    fn run(&mut self, max_generations: usize) -> Option<Cycle> {
+        match self.run_with(max_generations, |_| Ok(())) {
+            Ok(cycle) => cycle,
+            Err(_) => unreachable!(),
+        }
+    }
+
+    // As `run`, but first passes the starting state and then every new
+    // state to `observe`, stopping early if it fails. A repeated state is
+    // not passed on.
+    fn run_with<F>(
+        &mut self,
+        max_generations: usize,
+        mut observe: F,
+    ) -> Result<Option<Cycle>, Box<dyn Error>>
+    where
+        F: FnMut(&StateMachine<A>) -> Result<(), Box<dyn Error>>,
+    {
+        let initial = self.sm.state.clone();
+        let from = self.generation;
+        let mut seen: HashMap<u64, Vec<usize>> = HashMap::new();
+        seen.insert((self.hash)(&self.sm.state), vec![self.generation]);
+        observe(&self.sm)?;
+
+        while self.generation < max_generations {
+            if !self.step() {
+                return Ok(Some(Cycle {
+                    start: self.generation - 1,
+                    period: 1,
+                }));
+            }
+            let candidates = seen.entry((self.hash)(&self.sm.state)).or_default();
+            for &start in candidates.iter() {
+                if self.replay(&initial, from, start) == self.sm.state {
+                    return Ok(Some(Cycle {
+                        start,
+                        period: self.generation - start,
+                    }));
                 }
             }
+            candidates.push(self.generation);
+            observe(&self.sm)?;
         }
+        Ok(None)
+    }
+}
 
-        next
+fn run<A: Automaton>(sm: StateMachine<A>, max_generations: usize, threads: usize) -> Run<A> {
+    let mut simulation = Simulation::new(sm).with_threads(threads);
+    let cycle = simulation.run(max_generations);
+
+    Run {
+        generations: simulation.generation,
+        cycle,
+        state: simulation.into_state(),
     }
 }
 
-fn main() {
-    // Part 1
-    let mut sm = StateMachine::from_file("input.txt", Mode::Adjacency).unwrap();
+fn run_generations<A: Automaton>(
+    sm: StateMachine<A>,
+    generations: usize,
+    threads: usize,
+) -> StateMachine<A> {
+    let mut simulation = Simulation::new(sm).with_threads(threads);
 
-    loop {
-        let next = sm.next_state();
-        if next == sm {
-            println!(
-                "Found stable state with {} occupied seats",
-                sm.get_occupied_seats()
-            );
-            break;
+    // Once the pattern cycles, only the position within the cycle matters.
+    if let Some(cycle) = simulation.run(generations) {
+        let remaining = (generations - simulation.generation) % cycle.period;
+        for _ in 0..remaining {
+            simulation.step();
         }
+    }
+    simulation.into_state()
+}
+
+// Runs `sm` until it repeats, collecting statistics on every generation,
+// including the step back into the repeated state so that every flip in
+// the cycle is counted.
+fn collect_stats<A: Automaton>(
+    sm: StateMachine<A>,
+    max_generations: usize,
+    threads: usize,
+) -> Stats {
+    let mut stats = Stats::new();
+    let mut simulation = Simulation::new(sm).with_threads(threads);
+    let cycle = simulation.run_with(max_generations, |sm| {
+        stats.observe(sm);
+        Ok(())
+    });
+
+    stats.cycle = match cycle {
+        Ok(cycle) => cycle,
+        Err(_) => unreachable!(),
+    };
+    // A fixed point's last step changed nothing, so only longer cycles
+    // have a transition left to record.
+    if let Some(Cycle { period: 2.., .. }) = stats.cycle {
+        stats.observe(&simulation.sm);
+    }
+    stats
+}
 
-        sm = next;
+// Removes `flag` and its value from `args`, returning the value.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, Box<dyn Error>> {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => {
+            let value = args
+                .get(i + 1)
+                .ok_or(format!("Missing value for {}", flag))?
+                .clone();
+            args.drain(i..i + 2);
+            Ok(Some(value))
+        }
+        None => Ok(None),
     }
+}
 
-    let mut sm = StateMachine::from_file("input.txt", Mode::Visibility).unwrap();
+// Builds seating rules from `--threshold N`, `--radius N|none`,
+// `--blocking CELLS` and `--wrap`, starting from the puzzle's part 1 rules.
+fn take_seating_rules(args: &mut Vec<String>) -> Result<SeatingRules, Box<dyn Error>> {
+    let mut rules = Mode::Adjacency.rules();
 
-    loop {
-        let next = sm.next_state();
-        if next == sm {
-            println!(
-                "Found stable state with {} occupied seats",
-                sm.get_occupied_seats()
+    if let Some(n) = take_option(args, "--threshold")? {
+        rules.threshold = n.parse()?;
+    }
+    if let Some(n) = take_option(args, "--radius")? {
+        rules.radius = match n.as_str() {
+            "none" => None,
+            _ => Some(n.parse()?),
+        };
+    }
+    if let Some(cells) = take_option(args, "--blocking")? {
+        rules.blocking = cells.into_bytes();
+    }
+    if let Some(i) = args.iter().position(|a| a == "--wrap") {
+        rules.wrap = true;
+        args.remove(i);
+    }
+    rules.validate()?;
+    Ok(rules)
+}
+
+fn report<A: Automaton>(run: &Run<A>) {
+    match run.cycle {
+        Some(Cycle { start, period: 1 }) => println!(
+            "Found stable state at generation {} with {} occupied seats",
+            start,
+            run.state.get_occupied_seats()
+        ),
+        Some(Cycle { start, period }) => println!(
+            "Found cycle of period {} from generation {} with {} occupied seats",
+            period,
+            start,
+            run.state.get_occupied_seats()
+        ),
+        None => println!(
+            "No cycle within {} generations, {} occupied seats",
+            run.generations,
+            run.state.get_occupied_seats()
+        ),
+    }
+}
+
+// Plays or writes every generation of `sm` until it repeats, in the given
+// format: `ansi` to the terminal, `ppm` frames into the directory `output`
+// or `gif` to the file `output`.
+struct RenderOptions<'a> {
+    format: &'a str,
+    output: &'a str,
+    scale: usize,
+    delay: Duration,
+    max_generations: usize,
+    threads: usize,
+}
+
+fn render<A: Automaton>(
+    sm: StateMachine<A>,
+    options: &RenderOptions,
+) -> Result<Option<Cycle>, Box<dyn Error>> {
+    let mut exporter: Box<dyn Exporter> = match options.format {
+        "ansi" => Box::new(AnsiPlayer::new(io::stdout(), options.delay)),
+        "ppm" => Box::new(PpmWriter::new(options.output.into(), options.scale)?),
+        "gif" => Box::new(GifWriter::new(
+            BufWriter::new(File::create(options.output)?),
+            sm.size.x,
+            sm.size.y,
+            options.scale,
+            options.delay,
+        )?),
+        format => return Err(format!("Unknown format {}", format).into()),
+    };
+
+    Simulation::new(sm)
+        .with_threads(options.threads)
+        .run_with(options.max_generations, |sm| {
+            exporter.frame(sm.size.x, sm.size.y, sm.cells())
+        })
+}
+
+// Usage: day11 [--threads N] [--max-generations N] [life|brain FILE GENERATIONS]
+//        day11 [OPTIONS] [RULES] seating FILE
+//        day11 [OPTIONS] [RULES] stats csv|heatmap|json AUTOMATON FILE
+//        day11 [OPTIONS] [RULES] [--scale N] [--delay MS]
+//              render ansi|ppm|gif OUTPUT AUTOMATON FILE
+//
+// `--threads 0` uses every available core; the default is one. AUTOMATON is
+// one of adjacency, visibility, seating, life or brain. RULES configure
+// `seating`, as described at `take_seating_rules`.
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().collect::<Vec<String>>();
+    let mut threads = match take_option(&mut args, "--threads")? {
+        Some(n) => n.parse()?,
+        None => 1,
+    };
+    if threads == 0 {
+        threads = thread::available_parallelism()?.get();
+    }
+    let max_generations = match take_option(&mut args, "--max-generations")? {
+        Some(n) => n.parse()?,
+        None => 10_000,
+    };
+
+    let scale = match take_option(&mut args, "--scale")? {
+        Some(n) => n.parse()?,
+        None => 4,
+    };
+    let delay = match take_option(&mut args, "--delay")? {
+        Some(ms) => Duration::from_millis(ms.parse()?),
+        None => Duration::from_millis(200),
+    };
+    let rules = take_seating_rules(&mut args)?;
+
+    if args.get(1).map(|a| a.as_str()) == Some("render") {
+        let options = RenderOptions {
+            format: args.get(2).ok_or("Missing format")?,
+            output: args.get(3).ok_or("Missing output")?,
+            scale,
+            delay,
+            max_generations,
+            threads,
+        };
+        let automaton = args.get(4).ok_or("Missing automaton")?;
+        let file_name = args.get(5).ok_or("Missing file name")?;
+
+        let cycle = match automaton.as_str() {
+            "adjacency" => render(
+                StateMachine::from_file(file_name, Mode::Adjacency)?,
+                &options,
+            )?,
+            "visibility" => render(
+                StateMachine::from_file(file_name, Mode::Visibility)?,
+                &options,
+            )?,
+            "seating" => render(StateMachine::from_file(file_name, rules)?, &options)?,
+            "life" => render(StateMachine::from_file(file_name, Life)?, &options)?,
+            "brain" => render(StateMachine::from_file(file_name, BriansBrain)?, &options)?,
+            _ => return Err(format!("Unknown automaton {}", automaton).into()),
+        };
+        if let Some(Cycle { start, period }) = cycle {
+            eprintln!(
+                "Rendered until generation {} recurred every {}",
+                start, period
             );
-            break;
         }
+        return Ok(());
+    }
+
+    if args.get(1).map(|a| a.as_str()) == Some("stats") {
+        let format = args.get(2).ok_or("Missing format")?;
+        let automaton = args.get(3).ok_or("Missing automaton")?;
+        let file_name = args.get(4).ok_or("Missing file name")?;
 
-        sm = next;
+        let stats = match automaton.as_str() {
+            "adjacency" => collect_stats(
+                StateMachine::from_file(file_name, Mode::Adjacency)?,
+                max_generations,
+                threads,
+            ),
+            "visibility" => collect_stats(
+                StateMachine::from_file(file_name, Mode::Visibility)?,
+                max_generations,
+                threads,
+            ),
+            "seating" => collect_stats(
+                StateMachine::from_file(file_name, rules)?,
+                max_generations,
+                threads,
+            ),
+            "life" => collect_stats(
+                StateMachine::from_file(file_name, Life)?,
+                max_generations,
+                threads,
+            ),
+            "brain" => collect_stats(
+                StateMachine::from_file(file_name, BriansBrain)?,
+                max_generations,
+                threads,
+            ),
+            _ => return Err(format!("Unknown automaton {}", automaton).into()),
+        };
+        match format.as_str() {
+            "csv" => print!("{}", stats.to_csv()),
+            "heatmap" => print!("{}", stats.heatmap_csv()),
+            "json" => print!("{}", stats.to_json()),
+            _ => return Err(format!("Unknown format {}", format).into()),
+        }
+        return Ok(());
     }
+
+    if args.get(1).map(|a| a.as_str()) == Some("seating") {
+        let file_name = args.get(2).ok_or("Missing file name")?;
+        let result = run(
+            StateMachine::from_file(file_name, rules)?,
+            max_generations,
+            threads,
+        );
+        report(&result);
+        return Ok(());
+    }
+
+    if let Some(automaton) = args.get(1) {
+        let file_name = args.get(2).ok_or("Missing file name")?;
+        let generations = args.get(3).ok_or("Missing generation count")?.parse()?;
+
+        match automaton.as_str() {
+            "life" => print!(
+                "{}",
+                run_generations(
+                    StateMachine::from_file(file_name, Life)?,
+                    generations,
+                    threads
+                )
+            ),
+            "brain" => print!(
+                "{}",
+                run_generations(
+                    StateMachine::from_file(file_name, BriansBrain)?,
+                    generations,
+                    threads
+                )
+            ),
+            _ => return Err(format!("Unknown automaton {}", automaton).into()),
+        }
+        return Ok(());
+    }
+
+    // Part 1
+    report(&run(
+        StateMachine::from_file("input.txt", Mode::Adjacency)?,
+        max_generations,
+        threads,
+    ));
+
+    // Part 2
+    report(&run(
+        StateMachine::from_file("input.txt", Mode::Visibility)?,
+        max_generations,
+        threads,
+    ));
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -402,4 +1123,266 @@ LLLLLLL.LL
         assert_eq!(8, sm.get_neighbor_count(Pair { x: 5, y: 8 }));
         assert_eq!(2, sm.get_neighbor_count(Pair { x: 9, y: 9 }));
     }
+
+    #[test]
+    fn test_life_blinker() {
+        let sm = StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+        let next = sm.next_state();
+
+        assert_eq!(
+            next,
+            StateMachine::from_string(".....\n.....\n.###.\n.....\n.....\n", Life).unwrap()
+        );
+        assert_eq!(sm, next.next_state());
+    }
+
+    #[test]
+    fn test_brians_brain() {
+        let sm = StateMachine::from_string("......\n..##..\n......\n", BriansBrain).unwrap();
+
+        assert_eq!(
+            sm.next_state(),
+            StateMachine::from_string("..##..\n..oo..\n..##..\n", BriansBrain).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_alphabet() {
+        assert!(StateMachine::from_string("..L#\nLLoL\n", Mode::Adjacency).is_err());
+        assert!(StateMachine::from_string("..o#\n", BriansBrain).is_ok());
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let layout = "\
+L.LL.LL.LL
+LLLLLLL.LL
+L.L.L..L..
+LLLL.LL.LL
+L.LL.LL.LL
+L.LLLLL.LL
+..L.L.....
+LLLLLLLLLL
+L.LLLLLL.L
+L.LLLLL.LL
+";
+        for mode in [Mode::Adjacency, Mode::Visibility] {
+            let sm = StateMachine::from_string(layout, mode).unwrap();
+
+            for threads in 2..=11 {
+                let mut parallel = Simulation::new(sm.clone()).with_threads(threads);
+                let mut serial = Simulation::new(sm.clone());
+                loop {
+                    let changed = serial.step();
+                    assert_eq!(changed, parallel.step());
+                    assert_eq!(serial.sm, parallel.sm);
+                    if !changed {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let blinker =
+            StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+        let run_result = run(blinker.clone(), 100, 1);
+        assert_eq!(
+            Some(Cycle {
+                start: 0,
+                period: 2
+            }),
+            run_result.cycle
+        );
+        assert_eq!(2, run_result.generations);
+        assert_eq!(blinker, run_result.state);
+
+        let run_result = run(blinker.clone(), 1, 1);
+        assert_eq!(None, run_result.cycle);
+        assert_eq!(1, run_result.generations);
+
+        // A block is still from the start; a lone cell dies and stays dead.
+        let block = StateMachine::from_string("....\n.##.\n.##.\n....\n", Life).unwrap();
+        assert_eq!(
+            Some(Cycle {
+                start: 0,
+                period: 1
+            }),
+            run(block, 100, 1).cycle
+        );
+        let lone = StateMachine::from_string("...\n.#.\n...\n", Life).unwrap();
+        assert_eq!(
+            Some(Cycle {
+                start: 1,
+                period: 1
+            }),
+            run(lone, 100, 1).cycle
+        );
+    }
+
+    #[test]
+    fn test_run_hash_collisions() {
+        let blinker =
+            StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+        let mut simulation = Simulation::new(blinker.clone());
+        simulation.hash = |_| 0;
+
+        assert_eq!(
+            Some(Cycle {
+                start: 0,
+                period: 2
+            }),
+            simulation.run(100)
+        );
+        assert_eq!(2, simulation.generation);
+        assert_eq!(blinker, simulation.into_state());
+    }
+
+    #[test]
+    fn test_run_generations_skips_cycles() {
+        let blinker =
+            StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+
+        assert_eq!(
+            blinker.next_state(),
+            run_generations(blinker.clone(), 1_000_001, 1)
+        );
+        assert_eq!(blinker, run_generations(blinker.clone(), 1_000_000, 1));
+    }
+
+    #[test]
+    fn test_render_frames() {
+        let blinker =
+            StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+        let mut frames = Vec::new();
+
+        let cycle = Simulation::new(blinker)
+            .run_with(100, |sm| {
+                frames.push(sm.cells().to_vec());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(
+            Some(Cycle {
+                start: 0,
+                period: 2
+            }),
+            cycle
+        );
+        assert_eq!(
+            vec![
+                b".......#....#....#.......".to_vec(),
+                b"...........###...........".to_vec()
+            ],
+            frames
+        );
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let sm = StateMachine::from_string("..L#\nLLLL\n", Mode::Adjacency).unwrap();
+
+        assert_eq!(
+            sm,
+            StateMachine::from_string("..L#\nLLLL", Mode::Adjacency).unwrap()
+        );
+        assert_eq!(
+            sm,
+            StateMachine::from_string("..L#\r\nLLLL\r\n", Mode::Adjacency).unwrap()
+        );
+        assert_eq!(b"..L#LLLL", sm.cells());
+        assert_eq!("Size: (4, 2)\n..L#\nLLLL\n", sm.to_string());
+    }
+
+    #[test]
+    fn test_bad_layouts() {
+        assert!(StateMachine::from_string("", Mode::Adjacency).is_err());
+        assert!(StateMachine::from_string("\n\n", Mode::Adjacency).is_err());
+
+        let err = StateMachine::from_string("..L#\nLLL\n", Mode::Adjacency).unwrap_err();
+        assert_eq!(
+            "invalid format: row 2 has 3 cells, expected 4",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn test_seating_rules() {
+        let layout = "#.#.#\n.....\n#.L.#\n.....\n#.#.#\n";
+        let centre = Pair { x: 2, y: 2 };
+        let mut rules = Mode::Adjacency.rules();
+
+        let sm = StateMachine::from_string(layout, rules.clone()).unwrap();
+        assert_eq!(0, sm.get_neighbor_count(centre));
+
+        rules.radius = Some(2);
+        let sm = StateMachine::from_string(layout, rules.clone()).unwrap();
+        assert_eq!(8, sm.get_neighbor_count(centre));
+
+        // Floor that blocks sight hides everything.
+        rules.blocking = b".L#".to_vec();
+        let sm = StateMachine::from_string(layout, rules.clone()).unwrap();
+        assert_eq!(0, sm.get_neighbor_count(centre));
+
+        // Seats that don't block let sight pass to those behind them.
+        rules.radius = None;
+        rules.blocking = b"L#".to_vec();
+        let sm = StateMachine::from_string("#.#.L\n", rules.clone()).unwrap();
+        assert_eq!(1, sm.get_neighbor_count(Pair { x: 4, y: 0 }));
+        rules.blocking = Vec::new();
+        let sm = StateMachine::from_string("#.#.L\n", rules.clone()).unwrap();
+        assert_eq!(2, sm.get_neighbor_count(Pair { x: 4, y: 0 }));
+
+        rules.blocking = b"L".to_vec();
+        assert!(StateMachine::from_string(layout, rules).is_err());
+    }
+
+    #[test]
+    fn test_wraparound() {
+        let mut rules = Mode::Adjacency.rules();
+        let sm = StateMachine::from_string("#..\n...\n..L\n", rules.clone()).unwrap();
+        assert_eq!(0, sm.get_neighbor_count(Pair { x: 2, y: 2 }));
+
+        rules.wrap = true;
+        let sm = StateMachine::from_string("#..\n...\n..L\n", rules.clone()).unwrap();
+        assert_eq!(1, sm.get_neighbor_count(Pair { x: 2, y: 2 }));
+
+        // Unlimited sight around a torus stops on returning to the seat:
+        // only looking up and down finds an occupied seat.
+        rules.radius = None;
+        let sm = StateMachine::from_string("#..\nL..\n#..\n", rules).unwrap();
+        assert_eq!(2, sm.get_neighbor_count(Pair { x: 0, y: 1 }));
+    }
+
+    #[test]
+    fn test_stats() {
+        let blinker =
+            StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+        let stats = collect_stats(blinker, 100, 1);
+
+        assert_eq!(
+            Some(Cycle {
+                start: 0,
+                period: 2
+            }),
+            stats.cycle
+        );
+        assert_eq!(None, stats.converged_at());
+        assert_eq!(
+            "generation,changed,occupied\n0,0,3\n1,4,3\n2,4,3\n",
+            stats.to_csv()
+        );
+        assert_eq!(
+            "0,0,0,0,0\n0,0,2,0,0\n0,2,0,2,0\n0,0,2,0,0\n0,0,0,0,0\n",
+            stats.heatmap_csv()
+        );
+
+        let sm = StateMachine::from_string("L.L\n...\nL.L\n", Mode::Adjacency).unwrap();
+        let stats = collect_stats(sm, 100, 1);
+        assert_eq!(Some(1), stats.converged_at());
+        assert_eq!(4, stats.generations[1].changed);
+    }
 }
@@ -0,0 +1,321 @@
+use gif::{Encoder, Frame, Repeat};
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+// Colours for every cell any automaton uses; anything else is drawn white.
+const PALETTE: [(u8, [u8; 3]); 4] = [
+    (b'.', [48, 48, 48]),
+    (b'L', [80, 170, 90]),
+    (b'#', [220, 70, 60]),
+    (b'o', [70, 120, 220]),
+];
+const UNKNOWN: [u8; 3] = [255, 255, 255];
+
+fn palette_index(cell: u8) -> u8 {
+    PALETTE
+        .iter()
+        .position(|&(c, _)| c == cell)
+        .unwrap_or(PALETTE.len()) as u8
+}
+
+fn colour(cell: u8) -> [u8; 3] {
+    PALETTE
+        .iter()
+        .find(|&&(c, _)| c == cell)
+        .map_or(UNKNOWN, |&(_, rgb)| rgb)
+}
+
+// Receives one generation at a time as `width * height` cells in row-major
+// order.
+pub trait Exporter {
+    fn frame(&mut self, width: usize, height: usize, cells: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+// Plays generations back in the terminal, one coloured square per cell.
+pub struct AnsiPlayer<W: Write> {
+    out: W,
+    delay: Duration,
+    generation: usize,
+    // Reused for every frame.
+    text: String,
+}
+
+impl<W: Write> AnsiPlayer<W> {
+    pub fn new(out: W, delay: Duration) -> AnsiPlayer<W> {
+        AnsiPlayer {
+            out,
+            delay,
+            generation: 0,
+            text: String::new(),
+        }
+    }
+}
+
+impl<W: Write> Exporter for AnsiPlayer<W> {
+    fn frame(&mut self, width: usize, _: usize, cells: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.generation > 0 {
+            thread::sleep(self.delay);
+        }
+
+        // Clear the screen and home the cursor, then draw each cell as two
+        // spaces so that it comes out roughly square.
+        let text = &mut self.text;
+        text.clear();
+        writeln!(text, "\x1b[2J\x1b[HGeneration {}", self.generation)?;
+        for row in cells.chunks(width) {
+            for &cell in row {
+                let [r, g, b] = colour(cell);
+                write!(text, "\x1b[48;2;{};{};{}m  ", r, g, b)?;
+            }
+            text.push_str("\x1b[0m\n");
+        }
+        self.out.write_all(text.as_bytes())?;
+        self.out.flush()?;
+
+        self.generation += 1;
+        Ok(())
+    }
+}
+
+// Writes each generation to `DIR/frame_NNNNN.ppm`, `scale` pixels to a
+// cell.
+pub struct PpmWriter {
+    dir: PathBuf,
+    scale: usize,
+    generation: usize,
+    // One row of pixels, reused for every row.
+    line: Vec<u8>,
+}
+
+impl PpmWriter {
+    pub fn new(dir: PathBuf, scale: usize) -> Result<PpmWriter, Box<dyn Error>> {
+        fs::create_dir_all(&dir)?;
+        Ok(PpmWriter {
+            dir,
+            scale: scale.max(1),
+            generation: 0,
+            line: Vec::new(),
+        })
+    }
+
+    // Writes one frame as a binary PPM image to `out`.
+    fn write_ppm<O: Write>(
+        &mut self,
+        out: &mut O,
+        width: usize,
+        height: usize,
+        cells: &[u8],
+    ) -> Result<(), Box<dyn Error>> {
+        write!(
+            out,
+            "P6\n{} {}\n255\n",
+            width * self.scale,
+            height * self.scale
+        )?;
+        for row in cells.chunks(width) {
+            self.line.clear();
+            for &cell in row {
+                let rgb = colour(cell);
+                for _ in 0..self.scale {
+                    self.line.extend_from_slice(&rgb);
+                }
+            }
+            for _ in 0..self.scale {
+                out.write_all(&self.line)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Exporter for PpmWriter {
+    fn frame(&mut self, width: usize, height: usize, cells: &[u8]) -> Result<(), Box<dyn Error>> {
+        let path = self.dir.join(format!("frame_{:05}.ppm", self.generation));
+        let mut out = BufWriter::new(File::create(path)?);
+
+        self.write_ppm(&mut out, width, height, cells)?;
+        out.flush()?;
+
+        self.generation += 1;
+        Ok(())
+    }
+}
+
+// Encodes the generations as a looping animated GIF, `scale` pixels to a
+// cell.
+pub struct GifWriter<W: Write> {
+    encoder: Encoder<W>,
+    scale: usize,
+    // Time each frame is shown, in hundredths of a second.
+    delay: u16,
+}
+
+impl<W: Write> GifWriter<W> {
+    pub fn new(
+        out: W,
+        width: usize,
+        height: usize,
+        scale: usize,
+        delay: Duration,
+    ) -> Result<GifWriter<W>, Box<dyn Error>> {
+        let scale = scale.max(1);
+        let too_big = |n: usize| n * scale > u16::MAX as usize;
+        if too_big(width) || too_big(height) {
+            return Err(format!(
+                "{}x{} cells at scale {} is too large for a GIF",
+                width, height, scale
+            )
+            .into());
+        }
+
+        let palette = PALETTE
+            .iter()
+            .map(|&(_, rgb)| rgb)
+            .chain(Some(UNKNOWN))
+            .flat_map(|rgb| rgb.to_vec())
+            .collect::<Vec<u8>>();
+        let mut encoder = Encoder::new(
+            out,
+            (width * scale) as u16,
+            (height * scale) as u16,
+            &palette,
+        )?;
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        Ok(GifWriter {
+            encoder,
+            scale,
+            delay: (delay.as_millis() / 10).min(u16::MAX as u128) as u16,
+        })
+    }
+}
+
+impl<W: Write> Exporter for GifWriter<W> {
+    fn frame(&mut self, width: usize, _: usize, cells: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut pixels = Vec::with_capacity(cells.len() * self.scale * self.scale);
+        for row in cells.chunks(width) {
+            let line = row
+                .iter()
+                .flat_map(|&cell| iter::repeat_n(palette_index(cell), self.scale))
+                .collect::<Vec<u8>>();
+            for _ in 0..self.scale {
+                pixels.extend_from_slice(&line);
+            }
+        }
+
+        let width = (width * self.scale) as u16;
+        let height = (pixels.len() / width as usize) as u16;
+        let mut frame = Frame::from_indexed_pixels(width, height, &pixels, None);
+        frame.delay = self.delay;
+        self.encoder.write_frame(&frame)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Life, Simulation, StateMachine};
+
+    const RED: [u8; 3] = [220, 70, 60];
+    const GREY: [u8; 3] = [48, 48, 48];
+
+    // Runs the blinker until it repeats, passing both of its frames on.
+    fn render_blinker(exporter: &mut dyn Exporter) {
+        let blinker =
+            StateMachine::from_string(".....\n..#..\n..#..\n..#..\n.....\n", Life).unwrap();
+        Simulation::new(blinker)
+            .run_with(100, |sm| exporter.frame(sm.size.x, sm.size.y, sm.cells()))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_ansi() {
+        let mut out = Vec::new();
+        let mut player = AnsiPlayer::new(&mut out, Duration::from_millis(0));
+        player.frame(2, 1, b"#.").unwrap();
+        player.frame(1, 2, b"o?").unwrap();
+
+        assert_eq!(
+            concat!(
+                "\x1b[2J\x1b[HGeneration 0\n",
+                "\x1b[48;2;220;70;60m  \x1b[48;2;48;48;48m  \x1b[0m\n",
+                "\x1b[2J\x1b[HGeneration 1\n",
+                "\x1b[48;2;70;120;220m  \x1b[0m\n",
+                "\x1b[48;2;255;255;255m  \x1b[0m\n",
+            ),
+            String::from_utf8(out).unwrap()
+        );
+
+        let mut out = Vec::new();
+        render_blinker(&mut AnsiPlayer::new(&mut out, Duration::from_millis(0)));
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(2, text.matches("Generation").count());
+        assert_eq!(10, text.matches("\x1b[0m\n").count());
+    }
+
+    #[test]
+    fn test_ppm() {
+        let mut writer = PpmWriter {
+            dir: PathBuf::new(),
+            scale: 2,
+            generation: 0,
+            line: Vec::new(),
+        };
+        let mut out = Vec::new();
+        writer.write_ppm(&mut out, 2, 1, b".#").unwrap();
+
+        let header = b"P6\n4 2\n255\n";
+        assert_eq!(&header[..], &out[..header.len()]);
+        let row = [GREY, GREY, RED, RED].concat();
+        assert_eq!([row.clone(), row].concat(), &out[header.len()..]);
+    }
+
+    #[test]
+    fn test_ppm_blinker() {
+        let dir = std::env::temp_dir().join(format!("day11_ppm_{}", std::process::id()));
+        render_blinker(&mut PpmWriter::new(dir.clone(), 1).unwrap());
+
+        let frame = fs::read(dir.join("frame_00001.ppm")).unwrap();
+        let header = b"P6\n5 5\n255\n";
+        assert_eq!(&header[..], &frame[..header.len()]);
+        let pixels = frame[header.len()..].chunks(3).collect::<Vec<&[u8]>>();
+        assert_eq!(25, pixels.len());
+        for (i, pixel) in pixels.iter().enumerate() {
+            let live = (11..14).contains(&i);
+            assert_eq!(if live { &RED[..] } else { &GREY[..] }, *pixel);
+        }
+        assert!(!dir.join("frame_00002.ppm").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut out = Vec::new();
+        let mut writer = GifWriter::new(&mut out, 5, 5, 2, Duration::from_millis(100)).unwrap();
+        render_blinker(&mut writer);
+        drop(writer);
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&out[..]).unwrap();
+        assert_eq!((10, 10), (decoder.width(), decoder.height()));
+
+        let mut frames = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(10, frame.delay);
+            assert_eq!(100, frame.buffer.len());
+            frames += 1;
+        }
+        assert_eq!(2, frames);
+
+        assert!(GifWriter::new(Vec::new(), 40_000, 1, 2, Duration::from_millis(0)).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use crate::{Automaton, Cycle, StateMachine};
+
+// One generation of a run: how many cells changed since the previous
+// generation and how many are live (occupied, for seating).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Generation {
+    pub generation: usize,
+    pub changed: usize,
+    pub occupied: usize,
+}
+
+// Statistics gathered over a run, one generation at a time, along with
+// how often each cell flipped.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub width: usize,
+    pub height: usize,
+    pub generations: Vec<Generation>,
+    pub flips: Vec<usize>,
+    pub cycle: Option<Cycle>,
+    previous: Vec<u8>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    pub fn observe<A: Automaton>(&mut self, sm: &StateMachine<A>) {
+        let cells = sm.cells();
+        let mut changed = 0;
+
+        if self.generations.is_empty() {
+            self.width = sm.size.x;
+            self.height = sm.size.y;
+            self.flips = vec![0; cells.len()];
+        } else {
+            for (i, (&before, &after)) in self.previous.iter().zip(cells).enumerate() {
+                if before != after {
+                    self.flips[i] += 1;
+                    changed += 1;
+                }
+            }
+        }
+
+        self.generations.push(Generation {
+            generation: self.generations.len(),
+            changed,
+            occupied: sm.get_occupied_seats(),
+        });
+        self.previous.clear();
+        self.previous.extend_from_slice(cells);
+    }
+
+    // The generation from which nothing changes, if the run reached one.
+    pub fn converged_at(&self) -> Option<usize> {
+        match self.cycle {
+            Some(Cycle { start, period: 1 }) => Some(start),
+            _ => None,
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("generation,changed,occupied\n");
+        for g in &self.generations {
+            csv.push_str(&format!("{},{},{}\n", g.generation, g.changed, g.occupied));
+        }
+        csv
+    }
+
+    // How many times each cell flipped, one grid row per line.
+    pub fn heatmap_csv(&self) -> String {
+        self.flips
+            .chunks(self.width.max(1))
+            .map(|row| {
+                let counts = row.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+                format!("{}\n", counts.join(","))
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        let cycle = match self.cycle {
+            Some(Cycle { start, period }) => {
+                format!("{{\"start\": {}, \"period\": {}}}", start, period)
+            }
+            None => "null".to_string(),
+        };
+        let converged = match self.converged_at() {
+            Some(g) => g.to_string(),
+            None => "null".to_string(),
+        };
+        let generations = self
+            .generations
+            .iter()
+            .map(|g| {
+                format!(
+                    "    {{\"generation\": {}, \"changed\": {}, \"occupied\": {}}}",
+                    g.generation, g.changed, g.occupied
+                )
+            })
+            .collect::<Vec<String>>();
+        let heatmap = self
+            .flips
+            .chunks(self.width.max(1))
+            .map(|row| {
+                let counts = row.iter().map(|n| n.to_string()).collect::<Vec<String>>();
+                format!("    [{}]", counts.join(", "))
+            })
+            .collect::<Vec<String>>();
+
+        format!(
+            "{{\n  \"width\": {},\n  \"height\": {},\n  \"cycle\": {},\n  \"converged_at\": {},\n  \"generations\": [\n{}\n  ],\n  \"heatmap\": [\n{}\n  ]\n}}\n",
+            self.width,
+            self.height,
+            cycle,
+            converged,
+            generations.join(",\n"),
+            heatmap.join(",\n")
+        )
+    }
+}
@@ -1,18 +1,265 @@
+use std::cmp::Ordering;
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+use std::str::FromStr;
 
-const HEADINGS: [(i32, i32); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+const HEADINGS: [(i64, i64); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
 
-#[derive(Clone, Debug)]
-struct Point(i32, i32);
+// One coordinate. Turns by multiples of 90 degrees only swap and negate
+// coordinates, so routes made of them stay exact integers. Only a turn by
+// some other angle, or an integer overflow, switches to a float, so that
+// ships can still sail at any angle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Coord {
+    Exact(i64),
+    Float(f64),
+}
+
+use Coord::{Exact, Float};
+
+impl Coord {
+    fn to_f64(self) -> f64 {
+        match self {
+            Exact(n) => n as f64,
+            Float(x) => x,
+        }
+    }
+
+    // Combines two coordinates exactly if both are exact and the result
+    // fits, and as floats otherwise.
+    fn combine(
+        self,
+        other: Coord,
+        exact: fn(i64, i64) -> Option<i64>,
+        float: fn(f64, f64) -> f64,
+    ) -> Coord {
+        match (self, other) {
+            (Exact(a), Exact(b)) => {
+                exact(a, b).map_or_else(|| Float(float(a as f64, b as f64)), Exact)
+            }
+            _ => Float(float(self.to_f64(), other.to_f64())),
+        }
+    }
+
+    fn abs(self) -> Coord {
+        match self {
+            Exact(n) => n.checked_abs().map_or(Float((n as f64).abs()), Exact),
+            Float(x) => Float(x.abs()),
+        }
+    }
+
+    fn min(self, other: Coord) -> Coord {
+        if other < self {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn max(self, other: Coord) -> Coord {
+        if other > self {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl PartialOrd for Coord {
+    fn partial_cmp(&self, other: &Coord) -> Option<Ordering> {
+        match (self, other) {
+            (Exact(a), Exact(b)) => a.partial_cmp(b),
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+}
+
+impl Add for Coord {
+    type Output = Coord;
+
+    fn add(self, other: Coord) -> Coord {
+        self.combine(other, i64::checked_add, |a, b| a + b)
+    }
+}
+
+impl AddAssign for Coord {
+    fn add_assign(&mut self, other: Coord) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Coord {
+    type Output = Coord;
+
+    fn sub(self, other: Coord) -> Coord {
+        self.combine(other, i64::checked_sub, |a, b| a - b)
+    }
+}
+
+impl Mul for Coord {
+    type Output = Coord;
+
+    fn mul(self, other: Coord) -> Coord {
+        self.combine(other, i64::checked_mul, |a, b| a * b)
+    }
+}
+
+impl Neg for Coord {
+    type Output = Coord;
+
+    fn neg(self) -> Coord {
+        match self {
+            Exact(n) => n.checked_neg().map_or(Float(-(n as f64)), Exact),
+            Float(x) => Float(-x),
+        }
+    }
+}
+
+impl fmt::Display for Coord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exact(n) => write!(f, "{}", n),
+            Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Point(Coord, Coord);
+
+impl Point {
+    fn exact(x: i64, y: i64) -> Point {
+        Point(Exact(x), Exact(y))
+    }
+
+    fn manhattan(&self) -> Coord {
+        self.0.abs() + self.1.abs()
+    }
+
+    fn scale(&self, n: i32) -> Point {
+        Point(self.0 * Exact(n as i64), self.1 * Exact(n as i64))
+    }
+
+    // Rotates clockwise (with y pointing south) by `degrees` about the
+    // origin.
+    fn rotate(&self, degrees: i32) -> Point {
+        match degrees.rem_euclid(360) {
+            0 => *self,
+            90 => Point(-self.1, self.0),
+            180 => Point(-self.0, -self.1),
+            270 => Point(self.1, -self.0),
+            d => {
+                let (sin, cos) = (d as f64).to_radians().sin_cos();
+                let (x, y) = (self.0.to_f64(), self.1.to_f64());
+                Point(Float(x * cos - y * sin), Float(x * sin + y * cos))
+            }
+        }
+    }
+}
+
+// The unit vector for a compass heading in degrees: 0 is north and 90 east.
+fn heading_vector(heading: i32) -> Point {
+    let heading = heading.rem_euclid(360);
+    if heading % 90 == 0 {
+        let (x, y) = HEADINGS[(heading / 90) as usize];
+        Point::exact(x, y)
+    } else {
+        Point::exact(0, -1).rotate(heading)
+    }
+}
+
+// An angle in whole degrees; positive is clockwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Degrees(i32);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    North(i32),
+    South(i32),
+    East(i32),
+    West(i32),
+    Left(Degrees),
+    Right(Degrees),
+    Forward(i32),
+}
+
+impl FromStr for Command {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
+        let s = s.trim();
+        let action = s.chars().next().ok_or("empty command")?;
+        if !"NSEWLRF".contains(action) {
+            return Err(format!("unknown action {:?}", action).into());
+        }
+        let operand = &s[action.len_utf8()..];
+        let amount = operand
+            .parse::<i32>()
+            .map_err(|e| format!("invalid amount {:?} for {}: {}", operand, action, e))?;
+
+        Ok(match action {
+            'N' => Command::North(amount),
+            'S' => Command::South(amount),
+            'E' => Command::East(amount),
+            'W' => Command::West(amount),
+            'L' => Command::Left(Degrees(amount)),
+            'R' => Command::Right(Degrees(amount)),
+            _ => Command::Forward(amount),
+        })
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::North(n) => write!(f, "N{}", n),
+            Command::South(n) => write!(f, "S{}", n),
+            Command::East(n) => write!(f, "E{}", n),
+            Command::West(n) => write!(f, "W{}", n),
+            Command::Left(Degrees(d)) => write!(f, "L{}", d),
+            Command::Right(Degrees(d)) => write!(f, "R{}", d),
+            Command::Forward(n) => write!(f, "F{}", n),
+        }
+    }
+}
+
+// Parses a whole route, one command per line, so that it can be checked
+// before any ship sets sail. Blank lines are ignored.
+fn parse_route(content: &str) -> Result<Vec<Command>, Box<dyn Error>> {
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            line.parse()
+                .map_err(|e| format!("line {}: {}", i + 1, e).into())
+        })
+        .collect()
+}
+
+// Where the ship and its waypoint, relative to the ship, were after a
+// command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Fix {
+    pos: Point,
+    waypoint: Point,
+}
 
 struct Ship {
+    // Degrees clockwise from north, always in 0..360.
     heading: i32,
     pos: Point,
     waypoint: Point,
     mode: Mode,
+    // The starting fix and one more per command executed.
+    track: Vec<Fix>,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Mode {
     Normal,
     Waypoint,
@@ -22,104 +269,460 @@ impl Ship {
     pub fn new(mode: Mode) -> Ship {
         Ship {
             heading: 90,
-            pos: Point(0, 0),
-            waypoint: Point(10, -1),
+            pos: Point::exact(0, 0),
+            waypoint: Point::exact(10, -1),
             mode,
+            track: vec![Fix {
+                pos: Point::exact(0, 0),
+                waypoint: Point::exact(10, -1),
+            }],
         }
     }
 
-    fn perform_move(&mut self, delta_x: i32, delta_y: i32) {
-        if let Mode::Normal = self.mode {
-            self.pos.0 += delta_x;
-            self.pos.1 += delta_y;
+    fn perform_move(&mut self, delta: Point) {
+        let target = if let Mode::Normal = self.mode {
+            &mut self.pos
         } else {
-            self.waypoint.0 += delta_x;
-            self.waypoint.1 += delta_y;
-        }
+            &mut self.waypoint
+        };
+        target.0 += delta.0;
+        target.1 += delta.1;
     }
 
-    pub fn execute(&mut self, cmd: &str) {
-        let (order, operand) = cmd.split_at(1);
-        let amount = operand.parse::<i32>().unwrap();
-
-        match order {
-            "N" => self.perform_move(0, -amount),
-            "S" => self.perform_move(0, amount),
-            "W" => self.perform_move(-amount, 0),
-            "E" => self.perform_move(amount, 0),
-            "L" => {
-                if let Mode::Normal = self.mode {
-                    self.heading -= amount;
-
-                    if self.heading < 0 {
-                        self.heading = 360 + self.heading;
-                        self.heading = self.heading % 360;
-                    }
-                } else {
-                    let rotation = amount % 360;
-                    match rotation {
-                        270 => self.waypoint = Point(-self.waypoint.1, self.waypoint.0),
-                        180 => self.waypoint = Point(-self.waypoint.0, -self.waypoint.1),
-                        90 => self.waypoint = Point(self.waypoint.1, -self.waypoint.0),
-                        _ => panic!("Invalid rotation {}", rotation),
-                    };
-                }
-            }
-            "R" => {
+    pub fn execute(&mut self, cmd: Command) {
+        match cmd {
+            Command::North(n) => self.perform_move(Point::exact(0, -(n as i64))),
+            Command::South(n) => self.perform_move(Point::exact(0, n as i64)),
+            Command::West(n) => self.perform_move(Point::exact(-(n as i64), 0)),
+            Command::East(n) => self.perform_move(Point::exact(n as i64, 0)),
+            Command::Left(Degrees(d)) => self.turn(-(d % 360)),
+            Command::Right(Degrees(d)) => self.turn(d % 360),
+            Command::Forward(n) => {
                 if let Mode::Normal = self.mode {
-                    self.heading += amount;
-                    if self.heading >= 360 {
-                        self.heading = self.heading % 360;
-                    }
+                    self.perform_move(heading_vector(self.heading).scale(n))
                 } else {
-                    let rotation = amount % 360;
-                    match rotation {
-                        90 => self.waypoint = Point(-self.waypoint.1, self.waypoint.0),
-                        180 => self.waypoint = Point(-self.waypoint.0, -self.waypoint.1),
-                        270 => self.waypoint = Point(self.waypoint.1, -self.waypoint.0),
-                        _ => panic!("Invalid rotation {}", rotation),
-                    };
+                    let delta = self.waypoint.scale(n);
+                    self.pos.0 += delta.0;
+                    self.pos.1 += delta.1;
                 }
             }
-            "F" => {
-                if let Mode::Normal = self.mode {
-                    self.perform_move(
-                        amount * HEADINGS[(self.heading / 90) as usize].0,
-                        amount * HEADINGS[(self.heading / 90) as usize].1,
-                    )
-                } else {
-                    self.pos.0 += amount * self.waypoint.0;
-                    self.pos.1 += amount * self.waypoint.1;
-                }
+        }
+        self.track.push(Fix {
+            pos: self.pos,
+            waypoint: self.waypoint,
+        });
+    }
+
+    pub fn track(&self) -> &[Fix] {
+        &self.track
+    }
+
+    // The length of the ship's path, as the crow flies between fixes.
+    pub fn distance_travelled(&self) -> f64 {
+        self.track
+            .windows(2)
+            .map(|w| {
+                let dx = (w[1].pos.0 - w[0].pos.0).to_f64();
+                dx.hypot((w[1].pos.1 - w[0].pos.1).to_f64())
+            })
+            .sum()
+    }
+
+    // The smallest and largest coordinates the ship reached.
+    pub fn bounding_box(&self) -> (Point, Point) {
+        self.track
+            .iter()
+            .fold((self.track[0].pos, self.track[0].pos), |(min, max), fix| {
+                (
+                    Point(min.0.min(fix.pos.0), min.1.min(fix.pos.1)),
+                    Point(max.0.max(fix.pos.0), max.1.max(fix.pos.1)),
+                )
+            })
+    }
+
+    // The furthest the ship got from the origin, by Manhattan distance.
+    pub fn max_distance(&self) -> Coord {
+        self.track
+            .iter()
+            .map(|fix| fix.pos.manhattan())
+            .fold(Exact(0), Coord::max)
+    }
+
+    // Plots the ship's path in blue, from a green start to a red finish.
+    // Following the waypoint, each fix also gets a grey line out to where
+    // the waypoint was.
+    pub fn to_svg(&self) -> String {
+        let mut ends = self.track.iter().map(|fix| fix.pos).collect::<Vec<Point>>();
+        if let Mode::Waypoint = self.mode {
+            ends.extend(
+                self.track
+                    .iter()
+                    .map(|fix| Point(fix.pos.0 + fix.waypoint.0, fix.pos.1 + fix.waypoint.1)),
+            );
+        }
+        let (min, max) = ends.iter().fold((ends[0], ends[0]), |(min, max), p| {
+            (
+                Point(min.0.min(p.0), min.1.min(p.1)),
+                Point(max.0.max(p.0), max.1.max(p.1)),
+            )
+        });
+
+        // Leave a margin and size lines relative to the whole voyage.
+        let (min_x, min_y) = (min.0.to_f64(), min.1.to_f64());
+        let (width, height) = ((max.0 - min.0).to_f64(), (max.1 - min.1).to_f64());
+        let size = width.max(height).max(1.0);
+        let margin = size / 20.0;
+        let stroke = size / 500.0;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            min_x - margin,
+            min_y - margin,
+            width + 2.0 * margin,
+            height + 2.0 * margin
+        );
+        if let Mode::Waypoint = self.mode {
+            for fix in &self.track {
+                svg.push_str(&format!(
+                    "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"grey\" stroke-width=\"{}\"/>\n",
+                    fix.pos.0,
+                    fix.pos.1,
+                    fix.pos.0 + fix.waypoint.0,
+                    fix.pos.1 + fix.waypoint.1,
+                    stroke / 2.0
+                ));
             }
-            _ => panic!("Invalid instruction {}", order),
         }
+        let points = self
+            .track
+            .iter()
+            .map(|fix| format!("{},{}", fix.pos.0, fix.pos.1))
+            .collect::<Vec<String>>();
+        svg.push_str(&format!(
+            "  <polyline points=\"{}\" fill=\"none\" stroke=\"blue\" stroke-width=\"{}\"/>\n",
+            points.join(" "),
+            stroke
+        ));
+
+        let start = self.track[0].pos;
+        let end = self.pos;
+        for (p, colour) in &[(start, "green"), (end, "red")] {
+            svg.push_str(&format!(
+                "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"/>\n",
+                p.0,
+                p.1,
+                stroke * 4.0,
+                colour
+            ));
+        }
+        svg.push_str("</svg>\n");
+        svg
     }
+
+    // Turns the ship, or rotates the waypoint around it, clockwise by
+    // `degrees`; negative turns are anticlockwise.
+    fn turn(&mut self, degrees: i32) {
+        if let Mode::Normal = self.mode {
+            self.heading = (self.heading + degrees.rem_euclid(360)) % 360;
+        } else {
+            self.waypoint = self.waypoint.rotate(degrees);
+        }
+    }
+}
+
+fn report(part: usize, ship: &Ship) {
+    let (min, max) = ship.bounding_box();
+
+    println!(
+        "Part {}: got Manhattan distance: {} for position x: {}, y: {}",
+        part,
+        ship.pos.manhattan(),
+        ship.pos.0,
+        ship.pos.1
+    );
+    println!(
+        "    travelled {} over {} commands, at most {} from the origin, within ({}, {})-({}, {})",
+        ship.distance_travelled(),
+        ship.track().len() - 1,
+        ship.max_distance(),
+        min.0,
+        min.1,
+        max.0,
+        max.1
+    );
 }
 
-fn main() {
-    let mut file = File::open("input.txt").unwrap();
+// Usage: day12 [check | svg 1|2] [FILE]
+//
+// `check` only validates the route, printing it back in canonical form;
+// `svg` plots the voyage for part 1 or part 2.
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1).collect::<Vec<String>>();
+    let check = args.first().map(|a| a.as_str()) == Some("check");
+    if check {
+        args.remove(0);
+    }
+    let mut svg_part = None;
+    if args.first().map(|a| a.as_str()) == Some("svg") {
+        svg_part = Some(args.get(1).ok_or("Missing part")?.parse::<usize>()?);
+        args.drain(..2);
+    }
+    let file_name = args.first().map_or("input.txt", |a| a.as_str());
+
+    let mut file = File::open(file_name)?;
     let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let route = parse_route(&content)?;
+
+    if check {
+        for cmd in &route {
+            println!("{}", cmd);
+        }
+        return Ok(());
+    }
 
     let mut ship = Ship::new(Mode::Normal);
     let mut ship_part2 = Ship::new(Mode::Waypoint);
 
-    file.read_to_string(&mut content).unwrap();
-    for cmd in content.trim().lines() {
+    for &cmd in &route {
         ship.execute(cmd);
         ship_part2.execute(cmd);
     }
 
-    println!(
-        "Part 1: got Manhattan distance: {} for position x: {}, y: {}",
-        ship.pos.0.abs() + ship.pos.1.abs(),
-        ship.pos.0,
-        ship.pos.1
-    );
-    println!(
-        "Part 2: got Manhattan distance: {} for position x: {}, y: {}",
-        ship_part2.pos.0.abs() + ship_part2.pos.1.abs(),
-        ship_part2.pos.0,
-        ship_part2.pos.1
-    );
+    match svg_part {
+        Some(1) => print!("{}", ship.to_svg()),
+        Some(2) => print!("{}", ship_part2.to_svg()),
+        Some(part) => return Err(format!("No part {}", part).into()),
+        None => {
+            report(1, &ship);
+            report(2, &ship_part2);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::North(3), "N3".parse::<Command>().unwrap());
+        assert_eq!(
+            Command::Right(Degrees(90)),
+            " R90 ".parse::<Command>().unwrap()
+        );
+        assert_eq!(
+            Command::Left(Degrees(-45)),
+            "L-45".parse::<Command>().unwrap()
+        );
+
+        let error = |s: &str| s.parse::<Command>().unwrap_err().to_string();
+        assert_eq!("empty command", error(""));
+        assert_eq!("empty command", error("  \t"));
+        assert_eq!("unknown action 'X'", error("X10"));
+        // A multi-byte action must not split a character.
+        assert_eq!("unknown action 'é'", error("é10"));
+        assert_eq!("unknown action '北'", error("北"));
+        assert_eq!(
+            "invalid amount \"\" for F: cannot parse integer from empty string",
+            error("F")
+        );
+        assert_eq!(
+            "invalid amount \"1x\" for F: invalid digit found in string",
+            error("F1x")
+        );
+    }
+
+    #[test]
+    fn test_parse_route() {
+        let route = parse_route("F10\nN3\n\nF7\r\nR90\nF11").unwrap();
+        assert_eq!(
+            vec![
+                Command::Forward(10),
+                Command::North(3),
+                Command::Forward(7),
+                Command::Right(Degrees(90)),
+                Command::Forward(11),
+            ],
+            route
+        );
+
+        // Line numbers count blank lines too.
+        assert_eq!(
+            "line 4: invalid amount \"3x\" for N: invalid digit found in string",
+            parse_route("F10\n\nE2\nN3x\n").unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let text = "N1\nS-2\nE3\nW4\nL90\nR270\nF0";
+        let route = parse_route(text).unwrap();
+        let printed = route
+            .iter()
+            .map(|cmd| cmd.to_string())
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        assert_eq!(text, printed);
+        assert_eq!(route, parse_route(&printed).unwrap());
+    }
+
+    const EXAMPLE: &str = "F10\nN3\nF7\nR90\nF11\n";
+
+    fn sail(mode: Mode, route: &str) -> Ship {
+        let mut ship = Ship::new(mode);
+        for cmd in parse_route(route).unwrap() {
+            ship.execute(cmd);
+        }
+        ship
+    }
+
+    fn assert_near(expected: (f64, f64), actual: Point) {
+        assert!(matches!(actual, Point(Float(_), Float(_))), "{:?}", actual);
+        assert!(
+            (expected.0 - actual.0.to_f64()).abs() < 1e-9,
+            "{:?}",
+            actual
+        );
+        assert!(
+            (expected.1 - actual.1.to_f64()).abs() < 1e-9,
+            "{:?}",
+            actual
+        );
+    }
+
+    #[test]
+    fn test_example() {
+        let ship = sail(Mode::Normal, EXAMPLE);
+        assert_eq!(Point::exact(17, 8), ship.pos);
+        assert_eq!(Exact(25), ship.pos.manhattan());
+
+        let ship = sail(Mode::Waypoint, EXAMPLE);
+        assert_eq!(Point::exact(214, 72), ship.pos);
+        assert_eq!(Point::exact(4, 10), ship.waypoint);
+        assert_eq!(Exact(286), ship.pos.manhattan());
+        assert_eq!("214", ship.pos.0.to_string());
+    }
+
+    #[test]
+    fn test_turn_normalisation() {
+        for mode in [Mode::Normal, Mode::Waypoint] {
+            let left = sail(mode, "L90\nF10");
+            for route in ["L450\nF10", "R-90\nF10", "R270\nF10", "L-270\nF10"] {
+                let ship = sail(mode, route);
+                assert_eq!(left.pos, ship.pos, "{}", route);
+                assert_eq!(left.heading, ship.heading, "{}", route);
+            }
+        }
+        assert_eq!(0, sail(Mode::Normal, "L450").heading);
+        assert_eq!(0, sail(Mode::Normal, "R-90").heading);
+    }
+
+    #[test]
+    fn test_any_angle() {
+        let ship = sail(Mode::Normal, "R-45\nF10");
+        let side = 10.0 / 2f64.sqrt();
+        assert_near((side, -side), ship.pos);
+        assert_eq!(45, ship.heading);
+
+        // A full circle of 45 degree turns comes back to the same heading,
+        // but the coordinates stay floats.
+        let ship = sail(Mode::Normal, "R45\nR45\nR45\nR45\nR45\nR45\nR45\nR45\nF3");
+        assert_eq!(Point::exact(3, 0), ship.pos);
+
+        let ship = sail(Mode::Waypoint, "R45\nF2");
+        let waypoint = ((10.0 + 1.0) / 2f64.sqrt(), (10.0 - 1.0) / 2f64.sqrt());
+        assert_near(waypoint, ship.waypoint);
+        assert_near((2.0 * waypoint.0, 2.0 * waypoint.1), ship.pos);
+    }
+
+    #[test]
+    fn test_exact_beyond_f64() {
+        // 2 * 2147483647 * 1000000010 is above 2^53, where f64 would round.
+        let ship = sail(Mode::Waypoint, "E1000000000\nN-1\nF2147483647\nF2147483647");
+        assert_eq!(Point::exact(4294967336949672940, 0), ship.pos);
+        assert_eq!("4294967336949672940", ship.pos.0.to_string());
+
+        // Past i64, coordinates fall back to floats rather than wrapping.
+        let ship = sail(
+            Mode::Waypoint,
+            &format!("E1000000000\n{}", "F2147483647\n".repeat(5)),
+        );
+        assert_eq!(Exact(-5 * 2147483647), ship.pos.1);
+        match ship.pos.0 {
+            Float(x) => assert!((x / (5.0 * 2147483647.0 * 1000000010.0) - 1.0).abs() < 1e-12),
+            exact => panic!("{:?} should have overflowed", exact),
+        }
+    }
+
+    #[test]
+    fn test_track() {
+        let ship = sail(Mode::Normal, EXAMPLE);
+        assert_eq!(
+            vec![(0, 0), (10, 0), (10, -3), (17, -3), (17, -3), (17, 8)],
+            ship.track()
+                .iter()
+                .map(|fix| match fix.pos {
+                    Point(Exact(x), Exact(y)) => (x, y),
+                    pos => panic!("{:?} should be exact", pos),
+                })
+                .collect::<Vec<(i64, i64)>>()
+        );
+        assert_eq!(31.0, ship.distance_travelled());
+        assert_eq!(
+            (Point::exact(0, -3), Point::exact(17, 8)),
+            ship.bounding_box()
+        );
+        assert_eq!(Exact(25), ship.max_distance());
+
+        let ship = sail(Mode::Waypoint, EXAMPLE);
+        assert_eq!(6, ship.track().len());
+        assert_eq!(
+            Fix {
+                pos: Point::exact(170, -38),
+                waypoint: Point::exact(4, 10),
+            },
+            ship.track()[4]
+        );
+        let expected = 100f64.hypot(10.0) + 70f64.hypot(28.0) + 44f64.hypot(110.0);
+        assert!((expected - ship.distance_travelled()).abs() < 1e-9);
+        assert_eq!(
+            (Point::exact(0, -38), Point::exact(214, 72)),
+            ship.bounding_box()
+        );
+        assert_eq!(Exact(286), ship.max_distance());
+
+        // The furthest point need not be the last.
+        let ship = sail(Mode::Normal, "F10\nS2\nF-10");
+        assert_eq!(Exact(12), ship.max_distance());
+        assert_eq!(Exact(2), ship.pos.manhattan());
+    }
+
+    #[test]
+    fn test_to_svg() {
+        assert_eq!(
+            sail(Mode::Normal, EXAMPLE).to_svg(),
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-0.85 -3.85 18.7 12.7">
+  <polyline points="0,0 10,0 10,-3 17,-3 17,-3 17,8" fill="none" stroke="blue" stroke-width="0.034"/>
+  <circle cx="0" cy="0" r="0.136" fill="green"/>
+  <circle cx="17" cy="8" r="0.136" fill="red"/>
+</svg>
+"#
+        );
+
+        // The view takes in where the waypoint was, not only the ship.
+        let svg = sail(Mode::Waypoint, EXAMPLE).to_svg();
+        assert!(svg.starts_with(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-10.9 -52.9 239.8 145.8">"#
+        ));
+        assert!(svg.contains(r#"<polyline points="0,0 100,-10 100,-10 170,-38 170,-38 214,72" "#));
+        assert_eq!(6, svg.matches("<line ").count());
+        assert!(svg.contains(
+            r#"<line x1="214" y1="72" x2="218" y2="82" stroke="grey" stroke-width="0.218"/>"#
+        ));
+        assert!(svg.contains(r#"<circle cx="214" cy="72" r="1.744" fill="red"/>"#));
+    }
 }
@@ -1,79 +1,719 @@
-use std::collections::HashMap;
+use num_bigint::BigUint;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
 use std::io::Read;
 
+#[derive(Clone, Debug, PartialEq)]
 struct Capacity {
     name: String,
     count: usize,
 }
 
-fn parse_rule(s: &str) -> (String, Vec<Capacity>) {
-    let mut splits = s.split(" bags contain ");
-    let name = splits.next().unwrap().to_string();
-    let cap_string = splits.next().unwrap();
-    let mut caps = Vec::new();
-
-    if cap_string != "no other bags." {
-        for cap in cap_string.split(", ") {
-            let mut this_cap = cap.trim().trim_end_matches(".").splitn(2, " ");
-            let count = this_cap.next().unwrap().trim().parse().unwrap();
-            let mut name = this_cap.next().unwrap();
-            if name.ends_with(" bags") {
-                name = name.strip_suffix(" bags").unwrap();
-            } else if name.ends_with(" bag") {
-                name = name.strip_suffix(" bag").unwrap();
-            }
-            caps.push(Capacity {
-                name: name.to_string(),
-                count,
+type BagGraph = HashMap<String, Vec<Capacity>>;
+
+#[derive(Debug)]
+struct ParseError {
+    line: usize,
+    column: usize,
+    message: String,
+}
+
+impl Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+// Recursive-descent parser for one rule, e.g.
+//     light red bags contain 1 bright white bag, 2 muted yellow bags.
+// Runs of whitespace are insignificant, "bag" and "bags" are interchangeable
+// and the final period is optional.
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str, line: usize) -> Parser<'a> {
+        Parser { s, pos: 0, line }
+    }
+
+    fn error_at(&self, pos: usize, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            column: self.s[..pos].chars().count() + 1,
+            message,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.rest().chars().next()
+    }
+
+    fn peek_word(&mut self) -> (usize, &'a str) {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| c.is_whitespace() || c == ',' || c == '.')
+            .unwrap_or(rest.len());
+        (self.pos, &rest[..len])
+    }
+
+    fn word(&mut self) -> Result<(usize, &'a str), ParseError> {
+        let (pos, word) = self.peek_word();
+        if word.is_empty() {
+            return Err(match self.peek_char() {
+                Some(c) => self.error_at(pos, format!("unexpected '{}'", c)),
+                None => self.error_at(pos, "unexpected end of rule".to_string()),
             });
         }
+        self.pos += word.len();
+        Ok((pos, word))
+    }
+
+    fn keyword(&mut self, expected: &[&str]) -> Result<(), ParseError> {
+        let (pos, word) = self.word()?;
+        if expected.contains(&word) {
+            Ok(())
+        } else {
+            Err(self.error_at(pos, format!("expected '{}', found '{}'", expected[0], word)))
+        }
+    }
+
+    fn colour(&mut self) -> Result<String, ParseError> {
+        let mut words = Vec::new();
+
+        loop {
+            let (pos, word) = self.word()?;
+            match word {
+                "bag" | "bags" if words.is_empty() => {
+                    return Err(self.error_at(pos, "expected a colour".to_string()))
+                }
+                "bag" | "bags" => return Ok(words.join(" ")),
+                _ => words.push(word),
+            }
+        }
     }
 
-    (name, caps)
+    fn capacity(&mut self) -> Result<Capacity, ParseError> {
+        let (pos, word) = self.word()?;
+        let count = word
+            .parse()
+            .map_err(|_| self.error_at(pos, format!("expected a count, found '{}'", word)))?;
+
+        Ok(Capacity {
+            count,
+            name: self.colour()?,
+        })
+    }
+
+    fn rule(&mut self) -> Result<(String, Vec<Capacity>), ParseError> {
+        let name = self.colour()?;
+        let mut caps = Vec::new();
+
+        self.keyword(&["contain", "contains"])?;
+        if let (_, "no") = self.peek_word() {
+            self.keyword(&["no"])?;
+            self.keyword(&["other"])?;
+            self.keyword(&["bags", "bag"])?;
+        } else {
+            loop {
+                caps.push(self.capacity()?);
+                if self.peek_char() != Some(',') {
+                    break;
+                }
+                self.pos += 1;
+            }
+        }
+
+        if self.peek_char() == Some('.') {
+            self.pos += 1;
+        }
+        if let Some(c) = self.peek_char() {
+            return Err(self.error_at(self.pos, format!("unexpected '{}'", c)));
+        }
+
+        Ok((name, caps))
+    }
+}
+
+fn parse_rules(content: &str) -> Result<BagGraph, ParseError> {
+    let mut m = BagGraph::new();
+
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut parser = Parser::new(line, i + 1);
+        let (name, caps) = parser.rule()?;
+        if m.contains_key(&name) {
+            return Err(parser.error_at(0, format!("duplicate rule for {}", name)));
+        }
+        m.insert(name, caps);
+    }
+
+    Ok(m)
+}
+
+fn bags(count: usize) -> &'static str {
+    if count == 1 {
+        "bag"
+    } else {
+        "bags"
+    }
+}
+
+fn to_rules(m: &BagGraph) -> String {
+    let mut rules = String::new();
+
+    for name in sorted_names(m) {
+        let contents = if m[name].is_empty() {
+            "no other bags".to_string()
+        } else {
+            m[name]
+                .iter()
+                .map(|c| format!("{} {} {}", c.count, c.name, bags(c.count)))
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+        rules.push_str(&format!("{} bags contain {}.\n", name, contents));
+    }
+
+    rules
 }
 
-fn from_file(file_name: &str) -> HashMap<String, Vec<Capacity>> {
-    let mut file = File::open(file_name).unwrap();
+fn from_file(file_name: &str) -> Result<BagGraph, Box<dyn Error>> {
+    let mut file = File::open(file_name)?;
     let mut content = String::new();
-    let mut hm = HashMap::new();
 
-    file.read_to_string(&mut content).unwrap();
-    for (name, caps) in content.trim().lines().map(|x| parse_rule(x)) {
-        hm.insert(name, caps);
+    file.read_to_string(&mut content)?;
+    Ok(parse_rules(&content)?)
+}
+
+// Why a bag's capacity couldn't be counted.
+#[derive(Debug, PartialEq)]
+enum CapacityError {
+    NoRule(String),
+    // The bag ends up containing itself, so holds infinitely many bags.
+    Cycle(String),
+    Overflow(String),
+}
+
+impl Error for CapacityError {}
+
+impl fmt::Display for CapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CapacityError::NoRule(name) => write!(f, "no rule for {}", name),
+            CapacityError::Cycle(name) => write!(f, "{} bags contain themselves", name),
+            CapacityError::Overflow(name) => write!(f, "capacity of {} overflows usize", name),
+        }
+    }
+}
+
+// Memoised counts, with `None` marking a bag whose count is still being
+// worked out, so that reaching it again means a cycle.
+type Memo<'a, N> = HashMap<&'a str, Option<N>>;
+
+// Counts the bags inside `t` with a post-order walk over an explicit stack,
+// so that long chains of rules can't overflow the call stack. `add(total,
+// inner, count)` adds `count` bags each holding `inner` more to `total`,
+// returning `None` on overflow.
+fn count_bags<'a, N, F>(m: &'a BagGraph, t: &'a str, zero: N, add: F) -> Result<N, CapacityError>
+where
+    N: Clone,
+    F: Fn(&N, &N, usize) -> Option<N>,
+{
+    let contents = |name: &'a str| {
+        m.get(name)
+            .ok_or_else(|| CapacityError::NoRule(name.to_string()))
+    };
+    let mut memo: Memo<'a, N> = HashMap::new();
+    // Each bag being counted, its contents, how many of those have been
+    // added and the total so far.
+    let mut stack = vec![(t, contents(t)?, 0, zero.clone())];
+    memo.insert(t, None);
+
+    while let Some(&(name, caps, done, _)) = stack.last() {
+        let c = match caps.get(done) {
+            Some(c) => c,
+            None => {
+                let (_, _, _, total) = stack.pop().unwrap();
+                memo.insert(name, Some(total));
+                continue;
+            }
+        };
+
+        match memo.get(c.name.as_str()) {
+            Some(Some(inner)) => {
+                let frame = stack.last_mut().unwrap();
+                frame.3 = add(&frame.3, inner, c.count)
+                    .ok_or_else(|| CapacityError::Overflow(name.to_string()))?;
+                frame.2 += 1;
+            }
+            Some(None) => return Err(CapacityError::Cycle(c.name.clone())),
+            None => {
+                memo.insert(&c.name, None);
+                stack.push((&c.name, contents(&c.name)?, 0, zero.clone()));
+            }
+        }
+    }
+
+    Ok(memo.remove(t).flatten().unwrap())
+}
+
+fn count_capacity(m: &BagGraph, t: &str) -> Result<usize, CapacityError> {
+    // Each contained bag counts itself plus everything inside it.
+    count_bags(m, t, 0, |&total, &inner, count| {
+        inner.checked_add(1)?.checked_mul(count)?.checked_add(total)
+    })
+}
+
+fn count_capacity_big(m: &BagGraph, t: &str) -> Result<BigUint, CapacityError> {
+    count_bags(m, t, BigUint::from(0u32), |total, inner, count| {
+        Some(total + (inner + 1u32) * count)
+    })
+}
+
+// Generated rule sets can hold more than usize::MAX bags, so fall back to
+// arbitrary precision rather than wrapping.
+fn total_capacity(m: &BagGraph, t: &str) -> Result<BigUint, CapacityError> {
+    match count_capacity(m, t) {
+        Ok(n) => Ok(BigUint::from(n)),
+        Err(CapacityError::Overflow(_)) => count_capacity_big(m, t),
+        Err(e) => Err(e),
     }
-    hm
 }
 
-fn dfs(m: &HashMap<String, Vec<Capacity>>, v: &Vec<Capacity>, t: &str) -> usize {
-    for c in v {
-        if c.name == "shiny gold" || dfs(m, m.get(&c.name).unwrap(), t) == 1 {
-            return 1;
+// Every other bag that eventually holds `t`, found by walking the rules
+// backwards from it.
+fn holders_of<'a>(m: &'a BagGraph, t: &'a str) -> HashSet<&'a str> {
+    let mut holders: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, caps) in m {
+        for c in caps {
+            holders.entry(&c.name).or_default().push(name);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut stack = vec![t];
+    while let Some(name) = stack.pop() {
+        for &holder in holders.get(name).into_iter().flatten() {
+            if seen.insert(holder) {
+                stack.push(holder);
+            }
         }
     }
 
-    return 0;
+    seen.remove(t);
+    seen
 }
 
-fn count_capacity(m: &HashMap<String, Vec<Capacity>>, t: &str) -> usize {
-    m.get(t)
-        .unwrap()
+fn reachable_from(m: &BagGraph, t: &str) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![t.to_string()];
+
+    while let Some(name) = stack.pop() {
+        if seen.insert(name.clone()) {
+            if let Some(caps) = m.get(&name) {
+                stack.extend(caps.iter().map(|c| c.name.clone()));
+            }
+        }
+    }
+
+    seen
+}
+
+fn sorted_names(m: &BagGraph) -> Vec<&String> {
+    let mut names = m.keys().collect::<Vec<&String>>();
+    names.sort();
+    names
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn to_dot(m: &BagGraph, highlight: Option<&str>) -> Result<String, Box<dyn Error>> {
+    // Everything reachable from the highlighted bag is drawn in red, so the
+    // contents of a single bag stand out against the full rule set.
+    let reachable = match highlight {
+        Some(t) if !m.contains_key(t) => {
+            return Err(format!("no rule for {} to highlight", t).into())
+        }
+        Some(t) => reachable_from(m, t),
+        None => HashSet::new(),
+    };
+    let mut dot = String::from("digraph bags {\n");
+
+    for name in sorted_names(m) {
+        let style = if reachable.contains(name) {
+            " [color=red, fontcolor=red]"
+        } else {
+            ""
+        };
+        dot.push_str(&format!("    \"{}\"{};\n", escape(name), style));
+    }
+
+    for name in sorted_names(m) {
+        for c in &m[name] {
+            let style = if reachable.contains(name) {
+                ", color=red, fontcolor=red"
+            } else {
+                ""
+            };
+            dot.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\"{}];\n",
+                escape(name),
+                escape(&c.name),
+                c.count,
+                style
+            ));
+        }
+    }
+
+    dot.push_str("}\n");
+    Ok(dot)
+}
+
+fn to_json(m: &BagGraph) -> String {
+    let nodes = sorted_names(m)
         .iter()
-        .map(|c| c.count + c.count * count_capacity(m, &c.name))
-        .sum()
+        .map(|name| {
+            let edges = m[*name]
+                .iter()
+                .map(|c| {
+                    format!(
+                        "{{\"name\": \"{}\", \"count\": {}}}",
+                        escape(&c.name),
+                        c.count
+                    )
+                })
+                .collect::<Vec<String>>();
+            format!("  \"{}\": [{}]", escape(name), edges.join(", "))
+        })
+        .collect::<Vec<String>>();
+
+    format!("{{\n{}\n}}\n", nodes.join(",\n"))
 }
 
-fn main() {
-    let m = from_file("input.txt");
+fn main() -> Result<(), Box<dyn Error>> {
+    let m = from_file("input.txt")?;
+    let args = env::args().collect::<Vec<String>>();
+
+    // `day7 dot [bag]`, `day7 json` and `day7 rules` export the rules
+    // instead of solving.
+    match args.get(1).map(|a| a.as_str()) {
+        Some("dot") => {
+            print!("{}", to_dot(&m, args.get(2).map(|a| a.as_str()))?);
+            return Ok(());
+        }
+        Some("json") => {
+            print!("{}", to_json(&m));
+            return Ok(());
+        }
+        Some("rules") => {
+            print!("{}", to_rules(&m));
+            return Ok(());
+        }
+        _ => {}
+    }
 
     // Locate paths to shiny gold bags.
     println!(
         "Paths to shiny gold: {}",
-        m.keys()
-            .filter(|&k| k != "shiny gold")
-            .map(|k| dfs(&m, m.get(k).unwrap(), "shiny gold"))
-            .sum::<usize>()
+        holders_of(&m, "shiny gold").len()
     );
 
-    println!("Capacity of gold bag: {}", count_capacity(&m, "shiny gold"));
+    println!(
+        "Capacity of gold bag: {}",
+        total_capacity(&m, "shiny gold")?
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capacity(count: usize, name: &str) -> Capacity {
+        Capacity {
+            name: name.to_string(),
+            count,
+        }
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        let m = parse_rules(
+            "light red bags contain 1 bright white bag, 2 muted yellow bags.\n\
+             \n\
+             faded blue bags contain no other bags.\n",
+        )
+        .unwrap();
+
+        assert_eq!(m.len(), 2);
+        assert_eq!(
+            m["light red"],
+            vec![capacity(1, "bright white"), capacity(2, "muted yellow")]
+        );
+        assert_eq!(m["faded blue"], vec![]);
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let m = parse_rules(
+            "  light   red bag contains 1 bright  white bags ,2 muted yellow bag\n\
+             faded blue bag contain no other bag\t.",
+        )
+        .unwrap();
+
+        assert_eq!(
+            m["light red"],
+            vec![capacity(1, "bright white"), capacity(2, "muted yellow")]
+        );
+        assert_eq!(m["faded blue"], vec![]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |content| parse_rules(content).unwrap_err().to_string();
+
+        assert_eq!(
+            error(
+                "light red bags contain 1 bright white bag.\nlight red bags contain no other bags."
+            ),
+            "2:1: duplicate rule for light red"
+        );
+        assert_eq!(
+            error("light red bags hold 1 bright white bag."),
+            "1:16: expected 'contain', found 'hold'"
+        );
+        assert_eq!(
+            error("light red bags contain one bright white bag."),
+            "1:24: expected a count, found 'one'"
+        );
+        assert_eq!(
+            error("bags contain no other bags."),
+            "1:1: expected a colour"
+        );
+        assert_eq!(
+            error("light red bags contain 1 bright white"),
+            "1:38: unexpected end of rule"
+        );
+        assert_eq!(
+            error("light red bags contain no other bags. extra"),
+            "1:39: unexpected 'e'"
+        );
+        // Columns count characters, not bytes.
+        assert_eq!(
+            error("lïght red bags hold no other bags."),
+            "1:16: expected 'contain', found 'hold'"
+        );
+    }
+
+    const EXAMPLE: &str = "\
+light red bags contain 1 bright white bag, 2 muted yellow bags.
+dark orange bags contain 3 bright white bags, 4 muted yellow bags.
+bright white bags contain 1 shiny gold bag.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain 1 dark olive bag, 2 vibrant plum bags.
+dark olive bags contain 3 faded blue bags, 4 dotted black bags.
+vibrant plum bags contain 5 faded blue bags, 6 dotted black bags.
+faded blue bags contain no other bags.
+dotted black bags contain no other bags.
+";
+
+    const EXAMPLE2: &str = "\
+shiny gold bags contain 2 dark red bags.
+dark red bags contain 2 dark orange bags.
+dark orange bags contain 2 dark yellow bags.
+dark yellow bags contain 2 dark green bags.
+dark green bags contain 2 dark blue bags.
+dark blue bags contain 2 dark violet bags.
+dark violet bags contain no other bags.
+";
+
+    // `c0` holds `count` of `c1`, which holds `count` of `c2`, and so on
+    // down to `c{depth}`.
+    fn chain(depth: usize, count: usize) -> BagGraph {
+        let mut rules = (0..depth)
+            .map(|i| format!("c{} bags contain {} c{} bags.\n", i, count, i + 1))
+            .collect::<String>();
+        rules.push_str(&format!("c{} bags contain no other bags.\n", depth));
+        parse_rules(&rules).unwrap()
+    }
+
+    #[test]
+    fn test_count_capacity() {
+        let m = parse_rules(EXAMPLE).unwrap();
+        assert_eq!(Ok(32), count_capacity(&m, "shiny gold"));
+        assert_eq!(
+            Ok(BigUint::from(32u32)),
+            count_capacity_big(&m, "shiny gold")
+        );
+        assert_eq!(Ok(0), count_capacity(&m, "faded blue"));
+
+        let m = parse_rules(EXAMPLE2).unwrap();
+        assert_eq!(Ok(126), count_capacity(&m, "shiny gold"));
+        assert_eq!(Ok(BigUint::from(126u32)), total_capacity(&m, "shiny gold"));
+    }
+
+    #[test]
+    fn test_holders_of() {
+        let m = parse_rules(EXAMPLE).unwrap();
+        let mut holders = holders_of(&m, "shiny gold")
+            .into_iter()
+            .collect::<Vec<&str>>();
+        holders.sort_unstable();
+        assert_eq!(
+            vec!["bright white", "dark orange", "light red", "muted yellow"],
+            holders
+        );
+
+        let m = chain(100_000, 1);
+        assert_eq!(100_000, holders_of(&m, "c100000").len());
+    }
+
+    #[test]
+    fn test_capacity_errors() {
+        let m = parse_rules(
+            "shiny gold bags contain 1 dark red bag.\n\
+             dark red bags contain 2 dark blue bags, 1 shiny gold bag.\n\
+             dark blue bags contain no other bags.\n\
+             faded blue bags contain 1 faded blue bag.\n\
+             light red bags contain 1 muted yellow bag.\n",
+        )
+        .unwrap();
+
+        let cycle = Err(CapacityError::Cycle("shiny gold".to_string()));
+        assert_eq!(cycle, count_capacity(&m, "shiny gold"));
+        assert_eq!(cycle, count_capacity_big(&m, "shiny gold").map(|_| 0));
+        assert_eq!(
+            Err(CapacityError::Cycle("faded blue".to_string())),
+            count_capacity(&m, "faded blue")
+        );
+
+        let no_rule = Err(CapacityError::NoRule("muted yellow".to_string()));
+        assert_eq!(no_rule, count_capacity(&m, "light red"));
+        assert_eq!(no_rule, total_capacity(&m, "light red").map(|_| 0));
+        assert_eq!(
+            Err(CapacityError::NoRule("plaid".to_string())),
+            count_capacity(&m, "plaid")
+        );
+    }
+
+    #[test]
+    fn test_capacity_overflow() {
+        // 2 + 4 + ... + 2^70 bags.
+        let m = chain(70, 2);
+        let expected = BigUint::from(2u32).pow(71) - 2u32;
+
+        assert!(matches!(
+            count_capacity(&m, "c0"),
+            Err(CapacityError::Overflow(_))
+        ));
+        assert_eq!(Ok(expected), total_capacity(&m, "c0"));
+    }
+
+    #[test]
+    fn test_deep_chain() {
+        let m = chain(100_000, 1);
+
+        assert_eq!(Ok(100_000), count_capacity(&m, "c0"));
+        assert_eq!(Ok(BigUint::from(100_000u32)), count_capacity_big(&m, "c0"));
+    }
+
+    const SMALL: &str = "\
+light red bags contain 2 dark\"red bags.
+dark\"red bags contain 1 faded blue bag.
+faded blue bags contain no other bags.
+muted yellow bags contain 3 faded blue bags.
+";
+
+    #[test]
+    fn test_to_dot() {
+        let m = parse_rules(SMALL).unwrap();
+
+        assert_eq!(
+            to_dot(&m, None).unwrap(),
+            r#"digraph bags {
+    "dark\"red";
+    "faded blue";
+    "light red";
+    "muted yellow";
+    "dark\"red" -> "faded blue" [label="1"];
+    "light red" -> "dark\"red" [label="2"];
+    "muted yellow" -> "faded blue" [label="3"];
+}
+"#
+        );
+        assert_eq!(
+            to_dot(&m, Some("dark\"red")).unwrap(),
+            r#"digraph bags {
+    "dark\"red" [color=red, fontcolor=red];
+    "faded blue" [color=red, fontcolor=red];
+    "light red";
+    "muted yellow";
+    "dark\"red" -> "faded blue" [label="1", color=red, fontcolor=red];
+    "light red" -> "dark\"red" [label="2"];
+    "muted yellow" -> "faded blue" [label="3"];
+}
+"#
+        );
+        assert_eq!(
+            to_dot(&m, Some("shiny gold")).unwrap_err().to_string(),
+            "no rule for shiny gold to highlight"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let m = parse_rules(SMALL).unwrap();
+
+        assert_eq!(
+            to_json(&m),
+            r#"{
+  "dark\"red": [{"name": "faded blue", "count": 1}],
+  "faded blue": [],
+  "light red": [{"name": "dark\"red", "count": 2}],
+  "muted yellow": [{"name": "faded blue", "count": 3}]
+}
+"#
+        );
+        assert_eq!(escape(r"a\b"), r"a\\b");
+    }
+
+    #[test]
+    fn test_to_rules() {
+        let rules = "\
+bright white bags contain 1 shiny gold bag.
+dark orange bags contain 3 bright white bags, 4 muted yellow bags.
+faded blue bags contain no other bags.
+muted yellow bags contain 2 shiny gold bags, 9 faded blue bags.
+shiny gold bags contain no other bags.
+";
+        let m = parse_rules(rules).unwrap();
+
+        assert_eq!(to_rules(&m), rules);
+        assert_eq!(parse_rules(&to_rules(&m)).unwrap(), m);
+    }
 }
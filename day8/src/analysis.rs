@@ -0,0 +1,597 @@
+use crate::{Opcode, Termination, VM};
+use std::collections::VecDeque;
+use std::fmt;
+
+// Where control can go after an instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    To(usize),
+    // To the return node, shared by every `ret`, which leads just after
+    // every `call` and to `Exit`. An edge from each `ret` to each return
+    // site instead would make the graph quadratic in the program size.
+    Return,
+    // Normal termination: the end of the program, `hlt` or a final `ret`.
+    Exit,
+    OutOfRange(i64),
+}
+
+fn target(ip: usize, d: i32, len: usize) -> Edge {
+    let t = ip as i64 + d as i64;
+    if t >= 0 && t < len as i64 {
+        Edge::To(t as usize)
+    } else if t == len as i64 {
+        Edge::Exit
+    } else {
+        Edge::OutOfRange(t)
+    }
+}
+
+// Every edge the instruction at `ip` might take. Data-dependent
+// instructions get all of their possible successors; `ret` goes to the
+// return node.
+pub fn successors(ops: &[Opcode], ip: usize) -> Vec<Edge> {
+    let len = ops.len();
+    let next = target(ip, 1, len);
+    let mut edges = match ops[ip] {
+        Opcode::Jmp(d) | Opcode::Call(d) => vec![target(ip, d, len)],
+        Opcode::Jz(_, d) | Opcode::Jnz(_, d) | Opcode::Jgz(_, d) => {
+            vec![next, target(ip, d, len)]
+        }
+        // An empty stack makes `pop` fail rather than exit.
+        Opcode::Pop(_) => vec![next],
+        Opcode::Ret => vec![Edge::Return],
+        Opcode::Hlt => vec![Edge::Exit],
+        _ => vec![next],
+    };
+    edges.dedup();
+    edges
+}
+
+// Where `ret` may go: just after any `call`, or out of the outermost frame.
+fn return_sites(ops: &[Opcode]) -> Vec<Edge> {
+    let mut sites = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| matches!(op, Opcode::Call(_)))
+        .map(|(c, _)| target(c, 1, ops.len()))
+        .chain(Some(Edge::Exit))
+        .collect::<Vec<Edge>>();
+    sites.dedup();
+    sites
+}
+
+pub struct Cfg {
+    pub edges: Vec<Vec<Edge>>,
+    // The return node's edges, empty without any `ret`.
+    pub returns: Vec<Edge>,
+}
+
+impl Cfg {
+    pub fn new(ops: &[Opcode]) -> Cfg {
+        Cfg {
+            edges: (0..ops.len()).map(|ip| successors(ops, ip)).collect(),
+            returns: if ops.contains(&Opcode::Ret) {
+                return_sites(ops)
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.edges.len()
+    }
+
+    // The graph algorithms below number the return node `len()`, after the
+    // instructions, and drop it from their results.
+    fn out(&self, node: usize) -> &[Edge] {
+        self.edges.get(node).unwrap_or(&self.returns)
+    }
+
+    fn node(&self, edge: Edge) -> Option<usize> {
+        match edge {
+            Edge::To(ip) => Some(ip),
+            Edge::Return => Some(self.len()),
+            Edge::Exit | Edge::OutOfRange(_) => None,
+        }
+    }
+
+    // Instructions reachable from the entry point.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut seen = vec![false; self.len() + 1];
+        let mut queue = VecDeque::new();
+
+        if self.len() > 0 {
+            seen[0] = true;
+            queue.push_back(0);
+        }
+        while let Some(node) = queue.pop_front() {
+            for &edge in self.out(node) {
+                if let Some(next) = self.node(edge) {
+                    if !seen[next] {
+                        seen[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        seen.truncate(self.len());
+        seen
+    }
+
+    // Instructions from which some path reaches `Edge::Exit`. When every
+    // instruction has one successor, these are exactly the instructions from
+    // which the program terminates.
+    pub fn can_exit(&self) -> Vec<bool> {
+        let mut predecessors = vec![Vec::new(); self.len() + 1];
+        let mut exits = vec![false; self.len() + 1];
+        let mut queue = VecDeque::new();
+
+        let nodes = self.edges.iter().chain(Some(&self.returns));
+        for (node, edges) in nodes.enumerate() {
+            for &edge in edges {
+                if let Some(next) = self.node(edge) {
+                    predecessors[next].push(node);
+                } else if edge == Edge::Exit && !exits[node] {
+                    exits[node] = true;
+                    queue.push_back(node);
+                }
+            }
+        }
+        while let Some(node) = queue.pop_front() {
+            for &prev in &predecessors[node] {
+                if !exits[prev] {
+                    exits[prev] = true;
+                    queue.push_back(prev);
+                }
+            }
+        }
+        exits.truncate(self.len());
+        exits
+    }
+
+    // Strongly connected components containing a cycle, each sorted, in
+    // order of their lowest instruction. Uses an iterative Tarjan's
+    // algorithm so long programs cannot overflow the stack.
+    pub fn cycles(&self) -> Vec<Vec<usize>> {
+        let len = self.len() + 1;
+        let mut index = vec![usize::MAX; len];
+        let mut low = vec![0; len];
+        let mut on_stack = vec![false; len];
+        let mut stack = Vec::new();
+        let mut next_index = 0;
+        let mut components = Vec::new();
+
+        for root in 0..len {
+            if index[root] != usize::MAX {
+                continue;
+            }
+
+            // Each frame is an instruction and how many of its edges have
+            // been explored.
+            let mut frames = vec![(root, 0)];
+            index[root] = next_index;
+            low[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+
+            while let Some(&mut (ip, ref mut edge)) = frames.last_mut() {
+                if let Some(&e) = self.out(ip).get(*edge) {
+                    *edge += 1;
+                    if let Some(next) = self.node(e) {
+                        if index[next] == usize::MAX {
+                            index[next] = next_index;
+                            low[next] = next_index;
+                            next_index += 1;
+                            stack.push(next);
+                            on_stack[next] = true;
+                            frames.push((next, 0));
+                        } else if on_stack[next] {
+                            low[ip] = low[ip].min(index[next]);
+                        }
+                    }
+                    continue;
+                }
+
+                frames.pop();
+                if let Some(&(parent, _)) = frames.last() {
+                    low[parent] = low[parent].min(low[ip]);
+                }
+                if low[ip] == index[ip] {
+                    let mut component = Vec::new();
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == ip {
+                            break;
+                        }
+                    }
+                    if component.len() > 1 || self.out(ip).contains(&Edge::To(ip)) {
+                        component.retain(|&node| node != self.len());
+                        component.sort_unstable();
+                        components.push(component);
+                    }
+                }
+            }
+        }
+
+        components.sort();
+        components
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Halts,
+    // Follows a path that would halt, but the instruction at `ip` on it
+    // overflows an `i32` register.
+    Overflow { ip: usize },
+    // Control flow is fixed and revisits an instruction; `cycle` lists one
+    // iteration in execution order.
+    Loops { cycle: Vec<usize> },
+    OutOfRange { ip: usize, target: i64 },
+    // No path from the entry point terminates normally.
+    NeverHalts,
+    // Depends on register or stack contents.
+    Unknown,
+}
+
+// Flipping the `nop`/`jmp` at `index` makes the program terminate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fix {
+    pub index: usize,
+    pub original: Opcode,
+    pub replacement: Opcode,
+}
+
+fn flipped(op: Opcode) -> Option<Opcode> {
+    match op {
+        Opcode::Nop(d) => Some(Opcode::Jmp(d)),
+        Opcode::Jmp(d) => Some(Opcode::Nop(d)),
+        _ => None,
+    }
+}
+
+pub struct Analysis {
+    pub cfg: Cfg,
+    pub static_flow: bool,
+    pub unreachable: Vec<usize>,
+    pub cycles: Vec<Vec<usize>>,
+    pub verdict: Verdict,
+    pub fixes: Vec<Fix>,
+}
+
+// Follows the only possible path through a program without data-dependent
+// jumps, returning the instructions visited and how the path ends.
+fn follow(cfg: &Cfg) -> (Vec<usize>, Verdict) {
+    let mut path = Vec::new();
+    let mut position = vec![None; cfg.len()];
+    let mut edge = if cfg.len() > 0 {
+        Edge::To(0)
+    } else {
+        Edge::Exit
+    };
+
+    loop {
+        match edge {
+            Edge::To(ip) => {
+                if let Some(start) = position[ip] {
+                    let cycle = path[start..].to_vec();
+                    return (path, Verdict::Loops { cycle });
+                }
+                position[ip] = Some(path.len());
+                path.push(ip);
+                edge = cfg.edges[ip][0];
+            }
+            Edge::Return => unreachable!("static control flow has no ret"),
+            Edge::Exit => return (path, Verdict::Halts),
+            Edge::OutOfRange(target) => {
+                let ip = *path.last().unwrap();
+                return (path, Verdict::OutOfRange { ip, target });
+            }
+        }
+    }
+}
+
+pub fn analyse(ops: &[Opcode]) -> Analysis {
+    let cfg = Cfg::new(ops);
+    let static_flow = ops.iter().all(Opcode::is_static);
+    let reachable = cfg.reachable();
+    let can_exit = cfg.can_exit();
+    let cycles = cfg.cycles();
+    let mut fixes = Vec::new();
+
+    let verdict = if static_flow {
+        let (path, verdict) = follow(&cfg);
+
+        // Only an instruction on the path can change the outcome, and a
+        // flip fixes the program if its new successor terminates. That
+        // successor cannot lead back to the flipped instruction, which
+        // would otherwise terminate already.
+        if verdict != Verdict::Halts {
+            for &ip in &path {
+                if let Some(replacement) = flipped(ops[ip]) {
+                    let next = match replacement {
+                        Opcode::Jmp(d) => target(ip, d, ops.len()),
+                        _ => target(ip, 1, ops.len()),
+                    };
+                    let terminates = match next {
+                        Edge::To(t) => can_exit[t],
+                        Edge::Exit => true,
+                        Edge::Return | Edge::OutOfRange(_) => false,
+                    };
+                    if terminates {
+                        fixes.push(Fix {
+                            index: ip,
+                            original: ops[ip],
+                            replacement,
+                        });
+                    }
+                }
+            }
+        }
+        verdict
+    } else if !can_exit[0] {
+        Verdict::NeverHalts
+    } else if cycles.iter().all(|c| !reachable[c[0]])
+        && !ops.contains(&Opcode::Ret)
+        && !ops.iter().any(|op| matches!(op, Opcode::Pop(_)))
+        && (0..ops.len()).all(|ip| {
+            !reachable[ip]
+                || !cfg.edges[ip]
+                    .iter()
+                    .any(|e| matches!(e, Edge::OutOfRange(_)))
+        })
+    {
+        // Without reachable cycles or bad jumps every path ends normally.
+        // `ret` is excluded as its real target comes from the stack, and
+        // `pop` as it may underflow.
+        Verdict::Halts
+    } else {
+        Verdict::Unknown
+    };
+
+    // Programs take no input, so a halting verdict means the one execution
+    // never revisits an instruction. Running it takes at most one step per
+    // instruction and shows whether its arithmetic overflows.
+    let verdict = match verdict {
+        Verdict::Halts => match VM::new(ops.to_vec()).with_fuel(ops.len()).run() {
+            Termination::Overflow { ip, .. } => Verdict::Overflow { ip: ip as usize },
+            _ => Verdict::Halts,
+        },
+        verdict => verdict,
+    };
+
+    Analysis {
+        unreachable: (0..ops.len()).filter(|&ip| !reachable[ip]).collect(),
+        cycles,
+        cfg,
+        static_flow,
+        verdict,
+        fixes,
+    }
+}
+
+// Formats instruction indices compactly, e.g. "3, 7-9, 12".
+fn ranges(ips: &[usize]) -> String {
+    let mut parts = Vec::new();
+    let mut i = 0;
+
+    while i < ips.len() {
+        let mut j = i;
+        while j + 1 < ips.len() && ips[j + 1] == ips[j] + 1 {
+            j += 1;
+        }
+        if i == j {
+            parts.push(ips[i].to_string());
+        } else {
+            parts.push(format!("{}-{}", ips[i], ips[j]));
+        }
+        i = j + 1;
+    }
+    parts.join(", ")
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let edges = self.cfg.edges.iter().map(|e| e.len()).sum::<usize>() + self.cfg.returns.len();
+        writeln!(
+            f,
+            "Instructions: {} ({} edges, {} control flow)",
+            self.cfg.len(),
+            edges,
+            if self.static_flow {
+                "static"
+            } else {
+                "data-dependent"
+            }
+        )?;
+        writeln!(
+            f,
+            "Unreachable: {} [{}]",
+            self.unreachable.len(),
+            ranges(&self.unreachable)
+        )?;
+        writeln!(f, "Cycles: {}", self.cycles.len())?;
+        for cycle in &self.cycles {
+            writeln!(f, "    [{}]", ranges(cycle))?;
+        }
+
+        match &self.verdict {
+            Verdict::Halts => writeln!(f, "Verdict: halts")?,
+            Verdict::Overflow { ip } => writeln!(f, "Verdict: overflows at {}", ip)?,
+            Verdict::Loops { cycle } => writeln!(
+                f,
+                "Verdict: loops through {} instructions starting at {}",
+                cycle.len(),
+                cycle[0]
+            )?,
+            Verdict::OutOfRange { ip, target } => {
+                writeln!(f, "Verdict: jumps out of range from {} to {}", ip, target)?
+            }
+            Verdict::NeverHalts => writeln!(f, "Verdict: never halts")?,
+            Verdict::Unknown => writeln!(f, "Verdict: depends on data")?,
+        }
+
+        if !self.fixes.is_empty() {
+            writeln!(f, "Fixes: {}", self.fixes.len())?;
+            for fix in &self.fixes {
+                writeln!(
+                    f,
+                    "    {}: {} -> {}",
+                    fix.index, fix.original, fix.replacement
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+
+    const EXAMPLE: &str = "\
+nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+acc +1
+jmp -4
+acc +6
+";
+
+    #[test]
+    fn test_follow() {
+        let cfg = Cfg::new(&asm::assemble(EXAMPLE).unwrap());
+        let (path, verdict) = follow(&cfg);
+
+        assert_eq!(vec![0, 1, 2, 6, 7, 3, 4], path);
+        assert_eq!(
+            Verdict::Loops {
+                cycle: vec![1, 2, 6, 7, 3, 4]
+            },
+            verdict
+        );
+    }
+
+    #[test]
+    fn test_can_exit() {
+        let cfg = Cfg::new(&asm::assemble(EXAMPLE).unwrap());
+        let exits = cfg.can_exit();
+
+        assert_eq!(
+            vec![5, 8],
+            (0..9)
+                .filter(|&ip| !cfg.reachable()[ip])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(vec![8], (0..9).filter(|&ip| exits[ip]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_cycles() {
+        let cfg = Cfg::new(&asm::assemble(EXAMPLE).unwrap());
+        assert_eq!(vec![vec![1, 2, 3, 4, 6, 7]], cfg.cycles());
+
+        // A self-loop counts as a cycle; a lone instruction doesn't.
+        let cfg = Cfg::new(&asm::assemble("jmp +2\njmp +0\nnop +0\njmp -1\nhlt\n").unwrap());
+        assert_eq!(vec![vec![1], vec![2, 3]], cfg.cycles());
+    }
+
+    #[test]
+    fn test_analyse() {
+        let ops = asm::assemble(EXAMPLE).unwrap();
+        let analysis = analyse(&ops);
+
+        assert!(analysis.static_flow);
+        assert_eq!(vec![5, 8], analysis.unreachable);
+        assert_eq!(vec![vec![1, 2, 3, 4, 6, 7]], analysis.cycles);
+        assert_eq!(
+            vec![Fix {
+                index: 7,
+                original: Opcode::Jmp(-4),
+                replacement: Opcode::Nop(-4),
+            }],
+            analysis.fixes
+        );
+    }
+
+    #[test]
+    fn test_analyse_data_dependent() {
+        let ops = asm::assemble("set a 3\nloop: sub a 1\njnz a loop\nhlt\n").unwrap();
+        let analysis = analyse(&ops);
+
+        assert!(!analysis.static_flow);
+        assert_eq!(vec![vec![1, 2]], analysis.cycles);
+        assert_eq!(Verdict::Unknown, analysis.verdict);
+        assert!(analysis.fixes.is_empty());
+
+        let ops = asm::assemble("set a 3\njnz a end\nout a\nend: hlt\n").unwrap();
+        assert_eq!(Verdict::Halts, analyse(&ops).verdict);
+    }
+
+    const TRIANGULAR: &str = include_str!("../triangular.asm");
+
+    #[test]
+    fn test_return_node() {
+        let ops = asm::assemble(TRIANGULAR).unwrap();
+        let cfg = Cfg::new(&ops);
+
+        assert_eq!(vec![Edge::Return], cfg.edges[8]);
+        assert_eq!(vec![Edge::To(2), Edge::Exit], cfg.returns);
+        assert!(cfg.reachable().iter().all(|&r| r));
+        assert_eq!(vec![true; 9], cfg.can_exit());
+        // The loop runs through the subroutine, but the return node isn't
+        // an instruction.
+        assert_eq!(vec![vec![1, 2, 3, 4, 6, 7, 8]], cfg.cycles());
+
+        let analysis = analyse(&ops);
+        assert_eq!(Verdict::Unknown, analysis.verdict);
+        assert!(analysis
+            .to_string()
+            .starts_with("Instructions: 9 (12 edges, data-dependent control flow)\n"));
+
+        // Without `ret` there is no return node.
+        assert!(Cfg::new(&asm::assemble("call +1\nhlt\n").unwrap())
+            .returns
+            .is_empty());
+    }
+
+    #[test]
+    fn test_many_returns() {
+        // Each `ret` has one edge however many calls there are, so the graph
+        // stays linear in the program size.
+        let source = "call +1\nret\n".repeat(1000);
+        let cfg = Cfg::new(&asm::assemble(&source).unwrap());
+
+        assert_eq!(2000, cfg.edges.iter().map(|e| e.len()).sum::<usize>());
+        assert_eq!(1001, cfg.returns.len());
+        assert_eq!(Some(&Edge::Exit), cfg.returns.last());
+        assert_eq!(
+            vec![(1..2000).step_by(2).collect::<Vec<usize>>()],
+            cfg.cycles()
+        );
+    }
+
+    #[test]
+    fn test_analyse_overflow() {
+        let ops = asm::assemble("acc +2147483647\nacc +1\n").unwrap();
+        let analysis = analyse(&ops);
+        assert!(analysis.static_flow);
+        assert_eq!(Verdict::Overflow { ip: 1 }, analysis.verdict);
+        assert!(analysis.to_string().contains("Verdict: overflows at 1\n"));
+        assert!(analysis.fixes.is_empty());
+
+        let ops = asm::assemble("set b 2147483647\njnz b +2\nhlt\nadd b 1\n").unwrap();
+        assert_eq!(Verdict::Overflow { ip: 3 }, analyse(&ops).verdict);
+
+        // Overflow on a branch that isn't taken doesn't count.
+        let ops = asm::assemble("set b 2147483647\njz b +2\nhlt\nadd b 1\n").unwrap();
+        assert_eq!(Verdict::Halts, analyse(&ops).verdict);
+    }
+}
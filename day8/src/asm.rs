@@ -0,0 +1,283 @@
+use crate::{Opcode, REGISTERS};
+use std::collections::HashMap;
+use std::error::Error;
+
+// Assembly is the puzzle's instruction format plus:
+//   - comments, starting with `#` or `;` and running to the end of the line
+//   - labels, written `name:` either alone or before an instruction
+//   - jump and call targets given as a label instead of a relative offset;
+//     a label anywhere else is an error
+//
+//       set b 5
+//   loop:
+//       acc +2
+//       sub b 1
+//       jnz b loop   ; runs five times
+//       out a
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(['#', ';']) {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+// Splits a source line into its labels and the remaining instruction text.
+fn split_labels(line: &str) -> Result<(Vec<&str>, &str), String> {
+    let mut labels = Vec::new();
+    let mut rest = strip_comment(line).trim();
+
+    while let Some(i) = rest.find(':') {
+        let label = rest[..i].trim();
+        if !is_label(label) || REGISTERS.contains(&label) {
+            return Err(format!("Bad label {}", label));
+        }
+        labels.push(label);
+        rest = rest[i + 1..].trim();
+    }
+
+    Ok((labels, rest))
+}
+
+pub fn assemble(source: &str) -> Result<Vec<Opcode>, Box<dyn Error>> {
+    let mut labels = HashMap::new();
+    let mut instructions = Vec::new();
+
+    for (i, line) in source.lines().enumerate() {
+        let (line_labels, rest) =
+            split_labels(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+        for label in line_labels {
+            if labels.insert(label, instructions.len() as i32).is_some() {
+                return Err(format!("line {}: duplicate label {}", i + 1, label).into());
+            }
+        }
+        if !rest.is_empty() {
+            instructions.push((i + 1, rest));
+        }
+    }
+
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(ip, &(line, text))| {
+            resolve(text, ip, &labels).map_err(|e| format!("line {}: {}: {}", line, text, e).into())
+        })
+        .collect()
+}
+
+// Which operand of an instruction is its jump or call target.
+fn target_position(mnemonic: &str) -> Option<usize> {
+    match mnemonic {
+        "jmp" | "call" => Some(1),
+        "jz" | "jnz" | "jgz" => Some(2),
+        _ => None,
+    }
+}
+
+// Parses one instruction, turning a label in its target position into an
+// offset relative to `ip` so that the ordinary `Opcode` parser accepts it.
+// A label anywhere else is an error rather than a silent immediate.
+fn resolve(text: &str, ip: usize, labels: &HashMap<&str, i32>) -> Result<Opcode, Box<dyn Error>> {
+    let words = text.split_whitespace().collect::<Vec<&str>>();
+    let target = target_position(words[0]);
+    let mut resolved = Vec::with_capacity(words.len());
+
+    for (i, &word) in words.iter().enumerate() {
+        if i == 0 || !is_label(word) || REGISTERS.contains(&word) {
+            resolved.push(word.to_string());
+            continue;
+        }
+        match labels.get(word) {
+            Some(t) if target == Some(i) => resolved.push(format!("{:+}", t - ip as i32)),
+            Some(_) => return Err(format!("Label {} is not a jump or call target", word).into()),
+            None if target == Some(i) => return Err(format!("Unknown label {}", word).into()),
+            None => resolved.push(word.to_string()),
+        }
+    }
+
+    resolved.join(" ").parse()
+}
+
+fn jump_offset(op: &Opcode) -> Option<i32> {
+    match *op {
+        Opcode::Jmp(d) | Opcode::Jz(_, d) | Opcode::Jnz(_, d) | Opcode::Jgz(_, d) => Some(d),
+        Opcode::Call(d) => Some(d),
+        _ => None,
+    }
+}
+
+// Prints a program back as assembly, naming every in-range jump target
+// `L1`, `L2`, ... in address order. Targets outside the program keep their
+// numeric offset, so `assemble(&disassemble(ops))` always returns `ops`.
+pub fn disassemble(ops: &[Opcode]) -> String {
+    let mut targets = ops
+        .iter()
+        .enumerate()
+        .filter_map(|(ip, op)| jump_offset(op).map(|d| ip as i64 + d as i64))
+        .filter(|&t| t >= 0 && t <= ops.len() as i64)
+        .collect::<Vec<i64>>();
+    targets.sort_unstable();
+    targets.dedup();
+
+    let names = targets
+        .iter()
+        .enumerate()
+        .map(|(i, &t)| (t, format!("L{}", i + 1)))
+        .collect::<HashMap<i64, String>>();
+
+    let mut text = String::new();
+    for ip in 0..=ops.len() {
+        if let Some(name) = names.get(&(ip as i64)) {
+            text.push_str(&format!("{}:\n", name));
+        }
+        let op = match ops.get(ip) {
+            Some(op) => op,
+            None => break,
+        };
+
+        let line = match jump_offset(op).and_then(|d| names.get(&(ip as i64 + d as i64))) {
+            Some(name) => {
+                // Swap the trailing offset for the label name.
+                let op_text = op.to_string();
+                let (mnemonic, _) = op_text.rsplit_once(' ').unwrap();
+                format!("{} {}", mnemonic, name)
+            }
+            None => op.to_string(),
+        };
+        text.push_str(&format!("    {}\n", line));
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Operand;
+
+    #[test]
+    fn test_assemble() {
+        let source = "\
+# Counts down
+    set b 5   ; from five
+loop: acc +2
+    sub b 1
+    jnz b loop
+start: end: jmp end
+";
+        assert_eq!(
+            vec![
+                Opcode::Set(1, Operand::Imm(5)),
+                Opcode::Acc(2),
+                Opcode::Sub(1, Operand::Imm(1)),
+                Opcode::Jnz(Operand::Reg(1), -2),
+                Opcode::Jmp(0),
+            ],
+            assemble(source).unwrap()
+        );
+
+        // A label may follow the last instruction, and a numeric target
+        // needn't be in range.
+        assert_eq!(
+            vec![
+                Opcode::Call(3),
+                Opcode::Jz(Operand::Imm(0), -7),
+                Opcode::Ret
+            ],
+            assemble("call done\njz 0 -7\nret\ndone:\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let error = |s: &str| assemble(s).unwrap_err().to_string();
+        assert_eq!(
+            "line 2: set b loop: Label loop is not a jump or call target",
+            error("loop:\nset b loop\njmp loop\n")
+        );
+        assert_eq!(
+            "line 1: jz loop 2: Label loop is not a jump or call target",
+            error("loop: jz loop 2\n")
+        );
+        assert_eq!(
+            "line 1: push x: Label x is not a jump or call target",
+            error("push x\nx: hlt\n")
+        );
+        assert_eq!(
+            "line 1: jmp nowhere: Unknown label nowhere",
+            error("jmp nowhere\n")
+        );
+        assert_eq!("line 1: jz a: Missing operand for jz", error("jz a\n"));
+        assert_eq!(
+            "line 2: inc a: Unknown instruction inc",
+            error("x:\ninc a\n")
+        );
+        assert_eq!("line 3: duplicate label x", error("x: nop +0\n\nx:\n"));
+        assert_eq!("line 1: Bad label 1x", error("1x: hlt\n"));
+        assert_eq!("line 1: Bad label a", error("a: hlt\n"));
+    }
+
+    const TRIANGULAR: &str = include_str!("../triangular.asm");
+
+    #[test]
+    fn test_disassemble() {
+        assert_eq!(
+            "    set b 10
+L1:
+    call L2
+    out a
+    sub b 1
+    jnz b L1
+    hlt
+L2:
+    add c 1
+    add a c
+    ret
+",
+            disassemble(&assemble(TRIANGULAR).unwrap())
+        );
+
+        // Jumps to the end get a label; jumps outside the program don't.
+        assert_eq!(
+            "    jmp L1\n    jmp +5\nL1:\n",
+            disassemble(&[Opcode::Jmp(2), Opcode::Jmp(5)])
+        );
+        assert_eq!("", disassemble(&[]));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let ops = vec![
+            Opcode::Nop(-3),
+            Opcode::Acc(4),
+            Opcode::Jmp(3),
+            Opcode::Set(0, Operand::Reg(3)),
+            Opcode::Add(1, Operand::Imm(-2)),
+            Opcode::Sub(2, Operand::Reg(0)),
+            Opcode::Mul(3, Operand::Imm(7)),
+            Opcode::Jz(Operand::Reg(1), -7),
+            Opcode::Jnz(Operand::Imm(1), 5),
+            Opcode::Jgz(Operand::Reg(2), -100),
+            Opcode::Push(Operand::Imm(9)),
+            Opcode::Pop(1),
+            Opcode::Call(-12),
+            Opcode::Ret,
+            Opcode::Out(Operand::Reg(3)),
+            Opcode::Hlt,
+        ];
+        assert_eq!(ops, assemble(&disassemble(&ops)).unwrap());
+
+        let ops = assemble(TRIANGULAR).unwrap();
+        assert_eq!(ops, assemble(&disassemble(&ops)).unwrap());
+    }
+}
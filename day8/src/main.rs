@@ -1,117 +1,1023 @@
-use std::collections::HashSet;
+mod analysis;
+mod asm;
+mod repair;
+
+use core::str::FromStr;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
 use std::fs::File;
+use std::io;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::error::Error;
-use core::str::FromStr;
-use std::fmt;
+use std::io::Read;
+use std::io::Write;
 
 #[derive(Debug)]
-struct OpcodeErr;
+enum OpcodeErr {
+    Empty,
+    Unknown(String),
+    MissingOperand(String),
+}
+
 impl Error for OpcodeErr {}
 
 impl fmt::Display for OpcodeErr {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "OpcodeErr")
+        match self {
+            OpcodeErr::Empty => write!(f, "Empty instruction"),
+            OpcodeErr::Unknown(mnemonic) => write!(f, "Unknown instruction {}", mnemonic),
+            OpcodeErr::MissingOperand(mnemonic) => write!(f, "Missing operand for {}", mnemonic),
+        }
     }
 }
 
-#[derive(Copy, Clone)]
+const REGISTERS: [&str; 4] = ["a", "b", "c", "d"];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Operand {
+    Reg(usize),
+    Imm(i32),
+}
+
+impl FromStr for Operand {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
+        match REGISTERS.iter().position(|&r| r == s) {
+            Some(r) => Ok(Operand::Reg(r)),
+            None => Ok(Operand::Imm(s.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Reg(r) => write!(f, "{}", REGISTERS[*r]),
+            Operand::Imm(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+// The original handheld instructions are `nop`, `acc` and `jmp`; `acc`
+// adds to register `a`. Jump offsets are relative to the jumping
+// instruction, and `call` pushes the absolute return address.
+#[derive(Copy, Clone, Debug, PartialEq)]
 enum Opcode {
     Nop(i32),
     Acc(i32),
     Jmp(i32),
+    Set(usize, Operand),
+    Add(usize, Operand),
+    Sub(usize, Operand),
+    Mul(usize, Operand),
+    Jz(Operand, i32),
+    Jnz(Operand, i32),
+    Jgz(Operand, i32),
+    Push(Operand),
+    Pop(usize),
+    Call(i32),
+    Ret,
+    Out(Operand),
+    Hlt,
+}
+
+impl Opcode {
+    // Whether the instruction's successor depends only on its position and
+    // not on register or stack contents.
+    fn is_static(&self) -> bool {
+        !matches!(
+            self,
+            Opcode::Jz(..) | Opcode::Jnz(..) | Opcode::Jgz(..) | Opcode::Pop(_) | Opcode::Ret
+        )
+    }
+}
+
+fn register(s: &str) -> Result<usize, Box<dyn Error>> {
+    Ok(REGISTERS
+        .iter()
+        .position(|&r| r == s)
+        .ok_or(format!("Bad register {}", s))?)
 }
 
 impl FromStr for Opcode {
     type Err = Box<dyn Error>;
 
     fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
-        let mut halves = s.trim().split(" ");
-        match halves.next().ok_or(OpcodeErr)? {
-            "acc" => Ok(Opcode::Acc(halves.next().ok_or(OpcodeErr)?.parse()?)),
-            "jmp" => Ok(Opcode::Jmp(halves.next().ok_or(OpcodeErr)?.parse()?)),
-            "nop" => Ok(Opcode::Nop(halves.next().ok_or(OpcodeErr)?.parse()?)),
-            _ => Err(Box::new(OpcodeErr)),
+        let mut halves = s.split_whitespace();
+        let mnemonic = halves.next().ok_or(OpcodeErr::Empty)?;
+        let mut operand = || {
+            halves
+                .next()
+                .ok_or_else(|| OpcodeErr::MissingOperand(mnemonic.to_string()))
+        };
+        let op = match mnemonic {
+            "acc" => Opcode::Acc(operand()?.parse()?),
+            "jmp" => Opcode::Jmp(operand()?.parse()?),
+            "nop" => Opcode::Nop(operand()?.parse()?),
+            "set" => Opcode::Set(register(operand()?)?, operand()?.parse()?),
+            "add" => Opcode::Add(register(operand()?)?, operand()?.parse()?),
+            "sub" => Opcode::Sub(register(operand()?)?, operand()?.parse()?),
+            "mul" => Opcode::Mul(register(operand()?)?, operand()?.parse()?),
+            "jz" => Opcode::Jz(operand()?.parse()?, operand()?.parse()?),
+            "jnz" => Opcode::Jnz(operand()?.parse()?, operand()?.parse()?),
+            "jgz" => Opcode::Jgz(operand()?.parse()?, operand()?.parse()?),
+            "push" => Opcode::Push(operand()?.parse()?),
+            "pop" => Opcode::Pop(register(operand()?)?),
+            "call" => Opcode::Call(operand()?.parse()?),
+            "ret" => Opcode::Ret,
+            "out" => Opcode::Out(operand()?.parse()?),
+            "hlt" => Opcode::Hlt,
+            _ => return Err(OpcodeErr::Unknown(mnemonic.to_string()).into()),
+        };
+
+        match halves.next() {
+            Some(extra) => Err(format!("Unexpected operand {}", extra).into()),
+            None => Ok(op),
+        }
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Opcode::Acc(d) => write!(f, "acc {:+}", d),
+            Opcode::Jmp(d) => write!(f, "jmp {:+}", d),
+            Opcode::Nop(d) => write!(f, "nop {:+}", d),
+            Opcode::Set(r, x) => write!(f, "set {} {}", REGISTERS[*r], x),
+            Opcode::Add(r, x) => write!(f, "add {} {}", REGISTERS[*r], x),
+            Opcode::Sub(r, x) => write!(f, "sub {} {}", REGISTERS[*r], x),
+            Opcode::Mul(r, x) => write!(f, "mul {} {}", REGISTERS[*r], x),
+            Opcode::Jz(x, d) => write!(f, "jz {} {:+}", x, d),
+            Opcode::Jnz(x, d) => write!(f, "jnz {} {:+}", x, d),
+            Opcode::Jgz(x, d) => write!(f, "jgz {} {:+}", x, d),
+            Opcode::Push(x) => write!(f, "push {}", x),
+            Opcode::Pop(r) => write!(f, "pop {}", REGISTERS[*r]),
+            Opcode::Call(d) => write!(f, "call {:+}", d),
+            Opcode::Ret => write!(f, "ret"),
+            Opcode::Out(x) => write!(f, "out {}", x),
+            Opcode::Hlt => write!(f, "hlt"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Breakpoint {
+    Ip(i32),
+    Acc(i32),
+}
+
+impl FromStr for Breakpoint {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Box<dyn Error>> {
+        let mut halves = s.trim().split(' ');
+        match halves.next().ok_or("Empty breakpoint")? {
+            "ip" => Ok(Breakpoint::Ip(halves.next().ok_or("Missing ip")?.parse()?)),
+            "acc" => Ok(Breakpoint::Acc(
+                halves.next().ok_or("Missing acc")?.parse()?,
+            )),
+            _ => Err(format!("Bad breakpoint {}", s).into()),
+        }
+    }
+}
+
+// Why `VM::run_to_breakpoint` handed control back.
+#[derive(Debug, PartialEq)]
+enum Pause {
+    Breakpoint(Breakpoint),
+    Finished(Termination),
+}
+
+// How a program stopped. `acc` is always register `a` at that point.
+#[derive(Clone, Debug, PartialEq)]
+enum Termination {
+    // Ran off the end of the program, hit `hlt` or returned from the
+    // outermost frame.
+    Halted(i32),
+    // Would run forever; `cycle` lists the instructions of one iteration.
+    Loop { acc: i32, cycle: Vec<i32> },
+    // The instruction at `ip` transferred control to `target`, which is
+    // neither an instruction nor the end of the program.
+    OutOfRange { ip: i32, target: i64, acc: i32 },
+    // The instruction at `ip` overflowed an `i32` register.
+    Overflow { ip: i32, acc: i32 },
+    // `pop` at `ip` found the stack empty.
+    StackUnderflow { ip: i32, acc: i32 },
+    // Still running after `steps` instructions, the VM's fuel limit.
+    OutOfFuel { ip: i32, acc: i32, steps: usize },
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Termination::Halted(acc) => write!(f, "Terminated with acc {}", acc),
+            Termination::Loop { acc, cycle } => write!(
+                f,
+                "Infinite loop with acc {} through {}",
+                acc,
+                cycle
+                    .iter()
+                    .map(|ip| ip.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" -> ")
+            ),
+            Termination::OutOfRange { ip, target, acc } => write!(
+                f,
+                "Jump out of range from {} to {} with acc {}",
+                ip, target, acc
+            ),
+            Termination::Overflow { ip, acc } => {
+                write!(f, "Arithmetic overflow at {} with acc {}", ip, acc)
+            }
+            Termination::StackUnderflow { ip, acc } => {
+                write!(f, "Stack underflow at {} with acc {}", ip, acc)
+            }
+            Termination::OutOfFuel { ip, acc, steps } => write!(
+                f,
+                "Gave up after {} steps at {} with acc {}",
+                steps, ip, acc
+            ),
         }
     }
 }
 
+// The most instructions a VM executes before giving up, unless changed
+// with `VM::with_fuel`.
+const DEFAULT_FUEL: usize = 10_000_000;
+
 struct VM {
     ops: Vec<Opcode>,
     ip: i32,
-    acc: i32,
-    visited: HashSet<i32>,
+    regs: [i32; 4],
+    stack: Vec<i32>,
+    output: Vec<i32>,
+    // Programs without data-dependent jumps loop as soon as an instruction
+    // repeats, so `visited` maps each instruction to the step it first ran
+    // at and `history` holds every instruction run.
+    //
+    // Otherwise a loop is only certain once the whole machine state
+    // repeats. Rather than remember every state, Brent's algorithm keeps a
+    // single snapshot, retaken whenever `since_snapshot` reaches `power`,
+    // which doubles each time. A loop shows up as the state matching the
+    // snapshot, with `history` holding the instructions run since it.
+    static_flow: bool,
+    visited: HashMap<i32, usize>,
+    snapshot: Option<(i32, [i32; 4], Vec<i32>)>,
+    since_snapshot: usize,
+    power: usize,
+    history: Vec<i32>,
+    steps: usize,
+    fuel: usize,
+    finished: Option<Termination>,
 }
 
 impl VM {
     pub fn new(ops: Vec<Opcode>) -> VM {
         VM {
+            static_flow: ops.iter().all(Opcode::is_static),
             ops,
             ip: 0,
-            acc: 0,
-            visited: HashSet::new(),
+            regs: [0; 4],
+            stack: Vec::new(),
+            output: Vec::new(),
+            visited: HashMap::new(),
+            snapshot: None,
+            since_snapshot: 0,
+            power: 1,
+            history: Vec::new(),
+            steps: 0,
+            fuel: DEFAULT_FUEL,
+            finished: None,
         }
     }
 
-    pub fn run(&mut self) -> Result<i32, i32> {
+    pub fn with_fuel(mut self, fuel: usize) -> VM {
+        self.fuel = fuel;
+        self
+    }
+
+    pub fn ip(&self) -> i32 {
+        self.ip
+    }
+
+    pub fn acc(&self) -> i32 {
+        self.regs[0]
+    }
+
+    pub fn output(&self) -> &[i32] {
+        &self.output
+    }
+
+    pub fn current(&self) -> Option<Opcode> {
+        self.ops.get(self.ip as usize).copied()
+    }
+
+    fn value(&self, x: Operand) -> i32 {
+        match x {
+            Operand::Reg(r) => self.regs[r],
+            Operand::Imm(d) => d,
+        }
+    }
+
+    fn arithmetic(&mut self, r: usize, result: Option<i32>) -> Result<(), Termination> {
+        self.regs[r] = result.ok_or(Termination::Overflow {
+            ip: self.ip,
+            acc: self.acc(),
+        })?;
+        self.ip += 1;
+        Ok(())
+    }
+
+    fn jump_to(&mut self, target: i64) -> Result<(), Termination> {
+        if target < 0 || target > self.ops.len() as i64 {
+            return Err(Termination::OutOfRange {
+                ip: self.ip,
+                target,
+                acc: self.acc(),
+            });
+        }
+        self.ip = target as i32;
+        Ok(())
+    }
+
+    fn jump_by(&mut self, condition: bool, d: i32) -> Result<(), Termination> {
+        let offset = if condition { d } else { 1 };
+        self.jump_to(self.ip as i64 + offset as i64)
+    }
+
+    fn execute(&mut self, op: Opcode) -> Result<(), Termination> {
+        match op {
+            Opcode::Acc(d) => self.arithmetic(0, self.regs[0].checked_add(d)),
+            Opcode::Jmp(d) => self.jump_by(true, d),
+            Opcode::Nop(_) => self.jump_by(false, 0),
+            Opcode::Set(r, x) => self.arithmetic(r, Some(self.value(x))),
+            Opcode::Add(r, x) => self.arithmetic(r, self.regs[r].checked_add(self.value(x))),
+            Opcode::Sub(r, x) => self.arithmetic(r, self.regs[r].checked_sub(self.value(x))),
+            Opcode::Mul(r, x) => self.arithmetic(r, self.regs[r].checked_mul(self.value(x))),
+            Opcode::Jz(x, d) => self.jump_by(self.value(x) == 0, d),
+            Opcode::Jnz(x, d) => self.jump_by(self.value(x) != 0, d),
+            Opcode::Jgz(x, d) => self.jump_by(self.value(x) > 0, d),
+            Opcode::Push(x) => {
+                self.stack.push(self.value(x));
+                self.jump_by(false, 0)
+            }
+            Opcode::Pop(r) => match self.stack.pop() {
+                Some(v) => self.arithmetic(r, Some(v)),
+                None => Err(Termination::StackUnderflow {
+                    ip: self.ip,
+                    acc: self.acc(),
+                }),
+            },
+            Opcode::Call(d) => {
+                self.stack.push(self.ip + 1);
+                self.jump_by(true, d)
+            }
+            Opcode::Ret => match self.stack.pop() {
+                Some(ip) => self.jump_to(ip as i64),
+                // Returning from the outermost frame ends the program.
+                None => Err(Termination::Halted(self.acc())),
+            },
+            Opcode::Out(x) => {
+                self.output.push(self.value(x));
+                self.jump_by(false, 0)
+            }
+            Opcode::Hlt => Err(Termination::Halted(self.acc())),
+        }
+    }
+
+    // Executes a single instruction. Returns `None` while the program is
+    // still running, otherwise the same result as `run`. Once stopped, the
+    // VM keeps returning the same termination.
+    pub fn step(&mut self) -> Option<Termination> {
+        if self.finished.is_some() {
+            return self.finished.clone();
+        }
+
+        let cycle = if self.static_flow {
+            let step = self.history.len();
+            let first_seen = *self.visited.entry(self.ip).or_insert(step);
+            if first_seen != step {
+                Some(self.history[first_seen..].to_vec())
+            } else {
+                None
+            }
+        } else {
+            let repeated = match &self.snapshot {
+                Some((ip, regs, stack)) => {
+                    *ip == self.ip && *regs == self.regs && *stack == self.stack
+                }
+                None => false,
+            };
+            if repeated {
+                Some(self.history.clone())
+            } else {
+                if self.snapshot.is_none() || self.since_snapshot == self.power {
+                    self.snapshot = Some((self.ip, self.regs, self.stack.clone()));
+                    self.since_snapshot = 0;
+                    self.power *= 2;
+                    self.history.clear();
+                }
+                self.since_snapshot += 1;
+                None
+            }
+        };
+
+        let termination = if let Some(cycle) = cycle {
+            Err(Termination::Loop {
+                acc: self.acc(),
+                cycle,
+            })
+        } else {
+            // Running off the end needs no fuel, so a program that uses
+            // exactly its fuel still halts.
+            match self.current() {
+                None => Err(Termination::Halted(self.acc())),
+                Some(_) if self.steps == self.fuel => Err(Termination::OutOfFuel {
+                    ip: self.ip,
+                    acc: self.acc(),
+                    steps: self.steps,
+                }),
+                Some(op) => {
+                    self.history.push(self.ip);
+                    self.steps += 1;
+                    self.execute(op)
+                }
+            }
+        };
+
+        self.finished = termination.err();
+        self.finished.clone()
+    }
+
+    pub fn run(&mut self) -> Termination {
         loop {
-            if !self.visited.contains(&self.ip) {
-                self.visited.insert(self.ip);
+            if let Some(t) = self.step() {
+                return t;
+            }
+        }
+    }
 
-                if self.ip as usize == self.ops.len() {
-                    return Ok(self.acc);
+    // Runs until the program finishes or lands on a breakpoint. At least one
+    // instruction is executed, so calling this again resumes past the
+    // breakpoint that last paused it. An `acc` breakpoint only fires when acc
+    // changes to its value, not on every step while it stays there.
+    pub fn run_to_breakpoint(&mut self, breakpoints: &[Breakpoint]) -> Pause {
+        loop {
+            let before = self.acc();
+            if let Some(r) = self.step() {
+                return Pause::Finished(r);
+            }
+
+            for &bp in breakpoints {
+                let hit = match bp {
+                    Breakpoint::Ip(ip) => self.ip == ip,
+                    Breakpoint::Acc(acc) => self.acc() == acc && before != acc,
+                };
+                if hit {
+                    return Pause::Breakpoint(bp);
                 }
-                match self.ops[self.ip as usize] {
-                    Opcode::Acc(d) => {
-                        self.acc += d;
-                        self.ip += 1;
-                    }
-                    Opcode::Jmp(d) => {
-                        self.ip += d;
-                    }
-                    Opcode::Nop(_) => {
-                        self.ip += 1;
-                    }
+            }
+        }
+    }
+
+    // Like `run`, but writes one line per executed instruction to `out`,
+    // including the one that ended the program, such as a `hlt` or a bad
+    // jump. Detecting a loop, running out of fuel or running off the end
+    // executes nothing, so writes nothing.
+    pub fn run_traced<W: Write>(&mut self, out: &mut W) -> io::Result<Termination> {
+        loop {
+            let (ip, acc, steps) = (self.ip, self.acc(), self.steps);
+            let op = self.current();
+            let r = self.step();
+
+            if let Some(op) = op.filter(|_| self.steps > steps) {
+                let op = op.to_string();
+                writeln!(out, "{:>5}: {:<10} acc {} -> {}", ip, op, acc, self.acc())?;
+            }
+            if let Some(r) = r {
+                return Ok(r);
+            }
+        }
+    }
+}
+
+fn print_state(vm: &VM) {
+    match vm.current() {
+        Some(op) => println!("ip {}  acc {}  next {}", vm.ip(), vm.acc(), op),
+        None => println!("ip {}  acc {}  next <end>", vm.ip(), vm.acc()),
+    }
+}
+
+// A minimal interactive debugger reading commands from stdin:
+//   s                step one instruction
+//   c                continue to the next breakpoint
+//   b ip N | b acc N add a breakpoint
+//   p                print the current state
+//   q                quit
+fn debug(opcodes: Vec<Opcode>, fuel: usize) -> Result<(), Box<dyn Error>> {
+    let mut vm = VM::new(opcodes).with_fuel(fuel);
+    let mut breakpoints = Vec::new();
+
+    print_state(&vm);
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let line = line.trim();
+        let finished = match line.split(' ').next().unwrap_or("") {
+            "s" => vm.step(),
+            "c" => match vm.run_to_breakpoint(&breakpoints) {
+                Pause::Breakpoint(bp) => {
+                    println!("Hit breakpoint {:?}", bp);
+                    None
                 }
-            } else {
-                return Err(self.acc);
+                Pause::Finished(r) => Some(r),
+            },
+            "b" => {
+                match line[1..].parse() {
+                    Ok(bp) => breakpoints.push(bp),
+                    Err(e) => println!("{}", e),
+                }
+                None
+            }
+            "p" => None,
+            "q" => break,
+            _ => {
+                println!("Unknown command {}", line);
+                None
             }
+        };
+
+        match finished {
+            Some(t) => {
+                println!("{}", t);
+                break;
+            }
+            None => print_state(&vm),
         }
     }
+
+    Ok(())
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let file = BufReader::new(File::open("input.txt")?);
+fn read_source(file_name: &str) -> Result<String, Box<dyn Error>> {
+    let mut source = String::new();
+    File::open(file_name)?.read_to_string(&mut source)?;
+    Ok(source)
+}
 
-    let opcodes = file
+fn run_program(file_name: &str, fuel: usize) -> Result<(), Box<dyn Error>> {
+    let mut vm = VM::new(asm::assemble(&read_source(file_name)?)?).with_fuel(fuel);
+    let r = vm.run();
+
+    for value in vm.output() {
+        println!("{}", value);
+    }
+    println!("{}", r);
+    Ok(())
+}
+
+// The puzzle program, in the original format.
+fn read_puzzle() -> Result<Vec<Opcode>, Box<dyn Error>> {
+    BufReader::new(File::open("input.txt")?)
         .lines()
         .map(|x| x?.parse())
-        .collect::<Result<Vec<Opcode>, Box<dyn Error>>>()?;
+        .collect()
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    // `day8 run FILE`, `day8 disasm [FILE]` and `day8 analyse [FILE]` work
+    // on assembly source; `day8 trace` and `day8 debug` inspect the
+    // unpatched puzzle program. `--fuel STEPS`, anywhere, limits every run.
+    let mut args = env::args().collect::<Vec<String>>();
+    let mut fuel = DEFAULT_FUEL;
+    if let Some(i) = args.iter().position(|a| a == "--fuel") {
+        fuel = args.get(i + 1).ok_or("Missing fuel")?.parse()?;
+        args.drain(i..i + 2);
+    }
+    match args.get(1).map(|a| a.as_str()) {
+        Some("run") => return run_program(args.get(2).ok_or("Missing file name")?, fuel),
+        Some("disasm") => {
+            let file_name = args.get(2).map_or("input.txt", |a| a.as_str());
+            print!(
+                "{}",
+                asm::disassemble(&asm::assemble(&read_source(file_name)?)?)
+            );
+            return Ok(());
+        }
+        Some("analyse") => {
+            let file_name = args.get(2).map_or("input.txt", |a| a.as_str());
+            print!(
+                "{}",
+                analysis::analyse(&asm::assemble(&read_source(file_name)?)?)
+            );
+            return Ok(());
+        }
+        Some("trace") => {
+            let r = VM::new(read_puzzle()?)
+                .with_fuel(fuel)
+                .run_traced(&mut io::stdout().lock())?;
+            println!("{}", r);
+            return Ok(());
+        }
+        Some("debug") => return debug(read_puzzle()?, fuel),
+        _ => {}
+    }
 
-    if let Err(r) = VM::new(opcodes.clone()).run() {
-        println!("Part 1 value: {}", r);
+    let opcodes = read_puzzle()?;
+    match VM::new(opcodes.clone()).with_fuel(fuel).run() {
+        Termination::Loop { acc, .. } => println!("Part 1 value: {}", acc),
+        t => println!("Part 1: {}", t),
     }
 
     // Part 2
-    for (i, op) in opcodes.iter().enumerate() {
-        match op {
-            Opcode::Nop(d) => {
-                let mut mutated_ops = opcodes.clone();
-                mutated_ops[i] = Opcode::Jmp(*d);
-                if let Ok(r) = VM::new(mutated_ops).run() {
-                    println!("Part 2 value: {}", r);
-                }
+    let fix = repair::repair(&opcodes, fuel)?;
+    println!(
+        "Part 2 value: {} (changed {} at {} to {})",
+        fix.acc, fix.original, fix.index, fix.replacement
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str) -> (VM, Termination) {
+        let mut vm = VM::new(asm::assemble(source).unwrap());
+        let t = vm.run();
+        (vm, t)
+    }
+
+    #[test]
+    fn test_parse_opcode() {
+        let text = "nop -3\nacc +0\njmp +7\nset a -1\nadd b c\nsub c 2\nmul d a\n\
+                    jz a +2\njnz 1 -1\njgz d +0\npush 5\npush b\npop c\ncall -4\nret\nout d\nhlt";
+        let ops = text
+            .lines()
+            .map(|line| line.parse())
+            .collect::<Result<Vec<Opcode>, _>>()
+            .unwrap();
+        assert_eq!(Opcode::Add(1, Operand::Reg(2)), ops[4]);
+        assert_eq!(Opcode::Jnz(Operand::Imm(1), -1), ops[8]);
+        assert_eq!(
+            text,
+            ops.iter()
+                .map(|op| op.to_string())
+                .collect::<Vec<String>>()
+                .join("\n")
+        );
+
+        let error = |s: &str| s.parse::<Opcode>().unwrap_err().to_string();
+        assert_eq!("Empty instruction", error("  "));
+        assert_eq!("Unknown instruction inc", error("inc a"));
+        assert_eq!("Missing operand for jz", error("jz a"));
+        assert_eq!("Missing operand for pop", error("pop"));
+        assert_eq!("Bad register e", error("set e 1"));
+        assert_eq!("Unexpected operand 1", error("hlt 1"));
+        assert_eq!("invalid digit found in string", error("acc x"));
+    }
+
+    #[test]
+    fn test_original_opcodes() {
+        assert_eq!(Termination::Halted(-2), run("acc +3\nacc -5\n").1);
+        // `nop` ignores its operand.
+        assert_eq!(Termination::Halted(1), run("nop +100\nacc +1\n").1);
+        assert_eq!(Termination::Halted(2), run("jmp +2\nacc +1\nacc +2\n").1);
+    }
+
+    #[test]
+    fn test_arithmetic_opcodes() {
+        let (vm, t) = run("set b 6\nset c b\nadd c 4\nsub b 1\nmul c b\nset a c\nsub d 3\n");
+        assert_eq!(Termination::Halted(50), t);
+        assert_eq!([50, 5, 50, -3], vm.regs);
+        assert_eq!(7, vm.ip());
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        // Taken jumps skip the `acc`s; the final `jgz b` isn't taken.
+        let (vm, t) = run(
+            "jz b +2\nacc +1\nset b -1\njnz b +2\nacc +10\njgz b +2\nacc +100\n\
+             set c 1\njgz c +2\nacc +1000\njz c +2\njnz 0 +2\nacc +10000\n",
+        );
+        assert_eq!(Termination::Halted(10100), t);
+        assert_eq!(13, vm.ip());
+    }
+
+    #[test]
+    fn test_stack_opcodes() {
+        let (vm, t) = run("set b 3\npush 7\npush b\npop c\npop d\n");
+        assert_eq!(Termination::Halted(0), t);
+        assert_eq!([0, 3, 3, 7], vm.regs);
+        assert!(vm.stack.is_empty());
+
+        // `call` pushes the address after it for `ret`.
+        let (vm, t) = run("call +3\nacc +1\nhlt\nacc +5\nret\n");
+        assert_eq!(Termination::Halted(6), t);
+        assert_eq!(2, vm.ip());
+
+        // Returning with an empty stack ends the program.
+        let (vm, t) = run("acc +4\nret\nacc +1\n");
+        assert_eq!(Termination::Halted(4), t);
+        assert_eq!(1, vm.ip());
+    }
+
+    #[test]
+    fn test_out_and_hlt() {
+        let (vm, t) = run("set b 9\nout 3\nout b\nhlt\nout 4\n");
+        assert_eq!(Termination::Halted(0), t);
+        assert_eq!(&[3, 9], vm.output());
+        assert_eq!(3, vm.ip());
+    }
+
+    const EXAMPLE: &str = "\
+nop +0
+acc +1
+jmp +4
+acc +3
+jmp -3
+acc -99
+acc +1
+jmp -4
+acc +6
+";
+
+    #[test]
+    fn test_out_of_range() {
+        assert_eq!(
+            Termination::OutOfRange {
+                ip: 0,
+                target: -1,
+                acc: 0
+            },
+            run("jmp -1\n").1
+        );
+        let t = run("acc +1\njmp +5\nhlt\n").1;
+        assert_eq!(
+            Termination::OutOfRange {
+                ip: 1,
+                target: 6,
+                acc: 1
+            },
+            t
+        );
+        assert_eq!("Jump out of range from 1 to 6 with acc 1", t.to_string());
+        // `ret` checks the address it pops, and the offset is computed
+        // without wrapping.
+        assert_eq!(
+            Termination::OutOfRange {
+                ip: 1,
+                target: 9,
+                acc: 0
+            },
+            run("push 9\nret\n").1
+        );
+        assert_eq!(
+            Termination::OutOfRange {
+                ip: 1,
+                target: 2147483648,
+                acc: 0
+            },
+            run("nop +0\njmp +2147483647\n").1
+        );
+        // Jumping to just past the last instruction halts.
+        assert_eq!(Termination::Halted(0), run("jmp +2\nacc +1\n").1);
+    }
+
+    #[test]
+    fn test_overflow() {
+        let (vm, t) = run("acc +2147483647\nacc +1\n");
+        assert_eq!(
+            Termination::Overflow {
+                ip: 1,
+                acc: 2147483647
+            },
+            t
+        );
+        assert_eq!(1, vm.ip());
+
+        // The overflowing register keeps its old value.
+        let (vm, t) = run("set b 65536\nacc -3\nmul b b\n");
+        assert_eq!(Termination::Overflow { ip: 2, acc: -3 }, t);
+        assert_eq!(65536, vm.regs[1]);
+        assert_eq!("Arithmetic overflow at 2 with acc -3", t.to_string());
+
+        assert_eq!(
+            Termination::Overflow { ip: 1, acc: 0 },
+            run("set b -2147483648\nsub b 1\n").1
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        let t = run("acc +2\npush 1\npop b\npop c\n").1;
+        assert_eq!(Termination::StackUnderflow { ip: 3, acc: 2 }, t);
+        assert_eq!("Stack underflow at 3 with acc 2", t.to_string());
+    }
+
+    #[test]
+    fn test_out_of_fuel() {
+        // Counting up never repeats a state, so only fuel stops it.
+        let source = "loop: add b 1\njnz b loop\n";
+        let mut vm = VM::new(asm::assemble(source).unwrap()).with_fuel(101);
+        let t = vm.run();
+        assert_eq!(
+            Termination::OutOfFuel {
+                ip: 1,
+                acc: 0,
+                steps: 101
+            },
+            t
+        );
+        assert_eq!(51, vm.regs[1]);
+        assert_eq!("Gave up after 101 steps at 1 with acc 0", t.to_string());
+
+        // Fuel for every instruction is enough, one fewer isn't.
+        let ops = asm::assemble("acc +1\nacc +1\n").unwrap();
+        assert_eq!(
+            Termination::Halted(2),
+            VM::new(ops.clone()).with_fuel(2).run()
+        );
+        assert_eq!(
+            Termination::OutOfFuel {
+                ip: 1,
+                acc: 1,
+                steps: 1
             },
-            Opcode::Jmp(d) => {
-                let mut mutated_ops = opcodes.clone();
-                mutated_ops[i] = Opcode::Nop(*d);
-                if let Ok(r) = VM::new(mutated_ops).run() {
-                    println!("Part 2 value: {}", r);
+            VM::new(ops).with_fuel(1).run()
+        );
+        assert_eq!(Termination::Halted(0), VM::new(vec![]).with_fuel(0).run());
+    }
+
+    #[test]
+    fn test_static_loop() {
+        let (vm, t) = run(EXAMPLE);
+        let cycle = vec![1, 2, 6, 7, 3, 4];
+        assert_eq!(Termination::Loop { acc: 5, cycle }, t);
+        assert_eq!(1, vm.ip());
+        assert_eq!(
+            "Infinite loop with acc 5 through 1 -> 2 -> 6 -> 7 -> 3 -> 4",
+            t.to_string()
+        );
+    }
+
+    #[test]
+    fn test_data_dependent_loop() {
+        // Counts `b` down from 3 then resets it, so the state repeats every
+        // eight instructions. The cycle starts wherever Brent's snapshot
+        // was taken, but is exactly one period long.
+        let source = "set b 3\nloop: sub b 1\njgz b loop\nset b 3\njmp loop\n";
+        let (vm, t) = run(source);
+        let cycle = match t {
+            Termination::Loop { acc: 0, cycle } => cycle,
+            t => panic!("{:?} should be a loop", t),
+        };
+        assert_eq!(vec![2, 3, 4, 1, 2, 1, 2, 1], cycle);
+        assert_eq!(cycle[0], vm.ip());
+        let mut sorted = cycle.clone();
+        sorted.sort_unstable();
+        assert_eq!(vec![1, 1, 1, 2, 2, 2, 3, 4], sorted);
+
+        // Jumping back to a changed state isn't a loop.
+        assert_eq!(
+            Termination::Overflow { ip: 1, acc: 0 },
+            run("set b 1\nloop: mul b 2\njnz b loop\n").1
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let mut vm = VM::new(asm::assemble("acc +3\nhlt\nacc +1\n").unwrap());
+        assert_eq!(Some(Opcode::Acc(3)), vm.current());
+        assert_eq!(None, vm.step());
+        assert_eq!((1, 3), (vm.ip(), vm.acc()));
+        assert_eq!(Some(Opcode::Hlt), vm.current());
+
+        // Once finished, stepping executes nothing and repeats the result.
+        assert_eq!(Some(Termination::Halted(3)), vm.step());
+        assert_eq!(Some(Termination::Halted(3)), vm.step());
+        assert_eq!((1, 2), (vm.ip(), vm.steps));
+    }
+
+    #[test]
+    fn test_ip_breakpoint() {
+        // `out a` runs once per triangular number; each resume moves past
+        // the breakpoint to its next visit.
+        let mut vm = VM::new(asm::assemble(include_str!("../triangular.asm")).unwrap());
+        let breakpoints = [Breakpoint::Ip(2)];
+        let mut accs = Vec::new();
+        let finished = loop {
+            match vm.run_to_breakpoint(&breakpoints) {
+                Pause::Breakpoint(bp) => {
+                    assert_eq!(Breakpoint::Ip(2), bp);
+                    assert_eq!(2, vm.ip());
+                    accs.push(vm.acc());
                 }
+                Pause::Finished(t) => break t,
             }
-            _ => {}
-        }
+        };
+        assert_eq!(vec![1, 3, 6, 10, 15, 21, 28, 36, 45, 55], accs);
+        assert_eq!(Termination::Halted(55), finished);
+        assert_eq!(
+            Pause::Finished(Termination::Halted(55)),
+            vm.run_to_breakpoint(&breakpoints)
+        );
+
+        let mut vm = VM::new(asm::assemble(EXAMPLE).unwrap());
+        assert_eq!(
+            Pause::Breakpoint(Breakpoint::Ip(3)),
+            vm.run_to_breakpoint(&[Breakpoint::Ip(5), Breakpoint::Ip(3)])
+        );
+        assert_eq!(2, vm.acc());
+        assert!(matches!(
+            vm.run_to_breakpoint(&[Breakpoint::Ip(3)]),
+            Pause::Finished(Termination::Loop { acc: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_acc_breakpoint() {
+        // acc goes 1, 2, 2, 1, 2, 7: the breakpoint fires when it becomes 2,
+        // not again while the `nop` leaves it there.
+        let source = "acc +1\nacc +1\nnop +0\nacc -1\nacc +1\nacc +5\nhlt\n";
+        let mut vm = VM::new(asm::assemble(source).unwrap());
+        let breakpoints = [Breakpoint::Acc(2)];
+
+        assert_eq!(
+            Pause::Breakpoint(Breakpoint::Acc(2)),
+            vm.run_to_breakpoint(&breakpoints)
+        );
+        assert_eq!(2, vm.ip());
+        assert_eq!(
+            Pause::Breakpoint(Breakpoint::Acc(2)),
+            vm.run_to_breakpoint(&breakpoints)
+        );
+        assert_eq!(5, vm.ip());
+        assert_eq!(
+            Pause::Finished(Termination::Halted(7)),
+            vm.run_to_breakpoint(&breakpoints)
+        );
+
+        // acc starting at the value isn't a change to it.
+        let mut vm = VM::new(asm::assemble("nop +0\nacc +0\n").unwrap());
+        assert_eq!(
+            Pause::Finished(Termination::Halted(0)),
+            vm.run_to_breakpoint(&[Breakpoint::Acc(0)])
+        );
+
+        assert_eq!(Breakpoint::Acc(-4), " acc -4".parse().unwrap());
+        assert_eq!(Breakpoint::Ip(12), "ip 12".parse().unwrap());
+        let error = |s: &str| s.parse::<Breakpoint>().unwrap_err().to_string();
+        assert_eq!("Missing acc", error("acc"));
+        assert_eq!("Bad breakpoint pc 3", error("pc 3"));
+    }
+
+    fn trace(source: &str) -> (String, Termination) {
+        let mut vm = VM::new(asm::assemble(source).unwrap());
+        let mut out = Vec::new();
+        let t = vm.run_traced(&mut out).unwrap();
+        (String::from_utf8(out).unwrap(), t)
+    }
+
+    #[test]
+    fn test_run_traced() {
+        // The terminating `hlt` is traced; the skipped `acc` isn't.
+        assert_eq!(
+            (
+                "    0: acc +2     acc 0 -> 2
+    1: jmp +2     acc 2 -> 2
+    3: hlt        acc 2 -> 2
+"
+                .to_string(),
+                Termination::Halted(2)
+            ),
+            trace("acc +2\njmp +2\nacc +9\nhlt\n")
+        );
+
+        // So are a bad jump and an overflowing instruction.
+        let (text, t) = trace("acc +1\njmp -5\n");
+        assert_eq!(
+            "    0: acc +1     acc 0 -> 1\n    1: jmp -5     acc 1 -> 1\n",
+            text
+        );
+        assert!(matches!(t, Termination::OutOfRange { ip: 1, .. }));
+        let (text, _) = trace("acc +2147483647\nacc +1\n");
+        assert!(text.ends_with("    1: acc +1     acc 2147483647 -> 2147483647\n"));
+
+        // Running off the end and finding a loop execute nothing more.
+        assert_eq!("    0: nop +0     acc 0 -> 0\n", trace("nop +0\n").0);
+        let (text, t) = trace(EXAMPLE);
+        assert_eq!(7, text.lines().count());
+        assert!(text.ends_with("    4: jmp -3     acc 5 -> 5\n"));
+        assert!(matches!(t, Termination::Loop { acc: 5, .. }));
+    }
+
+    #[test]
+    fn test_triangular() {
+        let (vm, t) = run(include_str!("../triangular.asm"));
+        assert_eq!(Termination::Halted(55), t);
+        assert_eq!(&[1, 3, 6, 10, 15, 21, 28, 36, 45, 55], vm.output());
     }
-    Ok(())
 }
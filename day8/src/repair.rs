@@ -0,0 +1,80 @@
+use crate::analysis::{self, Verdict};
+use crate::{Opcode, Termination, VM};
+use std::error::Error;
+
+#[derive(Debug, PartialEq)]
+pub struct Repair {
+    pub index: usize,
+    pub original: Opcode,
+    pub replacement: Opcode,
+    pub acc: i32,
+}
+
+// Finds the single `nop`/`jmp` flip that makes the program terminate, in
+// linear time, using the first fix found by static analysis. The patched
+// program is run with at most `fuel` steps.
+pub fn repair(ops: &[Opcode], fuel: usize) -> Result<Repair, Box<dyn Error>> {
+    let analysis = analysis::analyse(ops);
+
+    if !analysis.static_flow {
+        return Err("Cannot repair a program with data-dependent control flow".into());
+    }
+    if analysis.verdict == Verdict::Halts {
+        return Err("Program already terminates".into());
+    }
+    let fix = analysis
+        .fixes
+        .first()
+        .ok_or("No single nop/jmp flip makes the program terminate")?;
+
+    let mut patched = ops.to_vec();
+    patched[fix.index] = fix.replacement;
+    let acc = match VM::new(patched).with_fuel(fuel).run() {
+        Termination::Halted(acc) => acc,
+        t => return Err(format!("Patched program failed: {}", t).into()),
+    };
+
+    Ok(Repair {
+        index: fix.index,
+        original: fix.original,
+        replacement: fix.replacement,
+        acc,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm, DEFAULT_FUEL};
+
+    #[test]
+    fn test_repair() {
+        let ops = asm::assemble(
+            "nop +0\nacc +1\njmp +4\nacc +3\njmp -3\nacc -99\nacc +1\njmp -4\nacc +6\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            Repair {
+                index: 7,
+                original: Opcode::Jmp(-4),
+                replacement: Opcode::Nop(-4),
+                acc: 8,
+            },
+            repair(&ops, DEFAULT_FUEL).unwrap()
+        );
+        assert_eq!(
+            "Patched program failed: Gave up after 3 steps at 6 with acc 1",
+            repair(&ops, 3).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn test_repair_errors() {
+        let halts = asm::assemble("acc +1\nhlt\n").unwrap();
+        assert!(repair(&halts, DEFAULT_FUEL).is_err());
+
+        let data_dependent = asm::assemble("set a 1\nloop: jnz a loop\n").unwrap();
+        assert!(repair(&data_dependent, DEFAULT_FUEL).is_err());
+    }
+}
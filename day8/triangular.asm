@@ -0,0 +1,13 @@
+# Prints the first ten triangular numbers, then halts.
+    set b 10
+loop:
+    call next       ; c += 1; a += c
+    out a
+    sub b 1
+    jnz b loop
+    hlt
+
+next:
+    add c 1
+    add a c
+    ret
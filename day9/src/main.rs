@@ -1,47 +1,417 @@
-use itertools::Itertools;
+use num_bigint::BigInt;
+use num_traits::{CheckedAdd, CheckedSub, Zero};
+use std::collections::{HashMap, VecDeque};
+use std::env;
 use std::error::Error;
+use std::fmt::Display;
 use std::fs::File;
+use std::hash::Hash;
 use std::io::BufRead;
 use std::io::BufReader;
+use std::ops::Range;
+use std::str::FromStr;
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let file = BufReader::new(File::open("input.txt")?);
-    const WINDOW_SIZE: usize = 25;
+// Anything the validator and weakness search can work over: the primitive
+// integers and `BigInt`. All arithmetic is checked.
+trait Number: Clone + Ord + Hash + Display + Zero + CheckedAdd + CheckedSub {}
+
+impl<T: Clone + Ord + Hash + Display + Zero + CheckedAdd + CheckedSub> Number for T {}
+
+// Checks an XMAS stream one number at a time. Every number after the
+// preamble must be the sum of two of the `preamble` numbers before it.
+// The sums of all pairs in the window are kept as a multiset, so each
+// number costs O(preamble) rather than O(preamble^2).
+struct XmasValidator<T> {
+    preamble: usize,
+    window: VecDeque<T>,
+    sums: HashMap<T, usize>,
+}
+
+impl<T: Number> XmasValidator<T> {
+    pub fn new(preamble: usize) -> XmasValidator<T> {
+        XmasValidator {
+            preamble,
+            window: VecDeque::with_capacity(preamble),
+            sums: HashMap::new(),
+        }
+    }
+
+    // Adds `n` to the stream, returning whether it is valid. Numbers in the
+    // preamble are always valid.
+    //
+    // A pair whose sum overflows `T` cannot add up to any `T`, so it is
+    // simply left out of the sums.
+    pub fn push(&mut self, n: T) -> bool {
+        let valid = self.window.len() < self.preamble || self.sums.contains_key(&n);
+
+        if self.preamble == 0 {
+            return valid;
+        }
+        if self.window.len() == self.preamble {
+            let old = self.window.pop_front().unwrap();
+            for other in &self.window {
+                if let Some(sum) = old.checked_add(other) {
+                    let count = self.sums.get_mut(&sum).unwrap();
+                    *count -= 1;
+                    if *count == 0 {
+                        self.sums.remove(&sum);
+                    }
+                }
+            }
+        }
+        for other in &self.window {
+            if let Some(sum) = n.checked_add(other) {
+                *self.sums.entry(sum).or_insert(0) += 1;
+            }
+        }
+        self.window.push_back(n);
+
+        valid
+    }
+
+    // Yields the index and value of every invalid number in `numbers`.
+    pub fn invalid<I: IntoIterator<Item = T>>(
+        mut self,
+        numbers: I,
+    ) -> impl Iterator<Item = (usize, T)> {
+        numbers
+            .into_iter()
+            .enumerate()
+            .filter(move |(_, n)| !self.push(n.clone()))
+    }
+}
+
+// A contiguous run of numbers summing to the target, and the encryption
+// weakness it yields.
+#[derive(Debug, PartialEq)]
+struct Weakness<T> {
+    range: Range<usize>,
+    min: T,
+    max: T,
+    weakness: T,
+}
+
+// Every run `s..e` of at least two numbers whose prefix sums differ by
+// `target`, i.e. `prefix[e] - prefix[s] == target`, ordered by start then
+// end. A map from prefix sums to their positions makes this one pass.
+fn matching_ranges<P: Hash + Eq + CheckedSub>(prefix: &[P], target: &P) -> Vec<Range<usize>> {
+    let mut starts: HashMap<&P, Vec<usize>> = HashMap::new();
+    let mut ranges = Vec::new();
+    for end in 2..prefix.len() {
+        // Only starts at least two before `end` are eligible. If the
+        // subtraction overflows, no prefix sum can match.
+        starts.entry(&prefix[end - 2]).or_default().push(end - 2);
+        if let Some(matches) = prefix[end].checked_sub(target).and_then(|w| starts.get(&w)) {
+            ranges.extend(matches.iter().map(|&start| start..end));
+        }
+    }
+    ranges.sort_by_key(|r| (r.start, r.end));
+    ranges
+}
+
+// Finds every contiguous run of at least two numbers summing to `target`,
+// ordered by start then end.
+//
+// The prefix sums are kept in `T` where they fit. The running total can
+// outgrow `T` even where every matching run fits, so then they are redone
+// as `BigInt`.
+//
+// Runs sharing a start are scanned together for their minimum and maximum,
+// so beyond the one pass each start costs the length of its longest run.
+// Inputs with many overlapping runs, such as all zeros with a target of
+// zero, can still take quadratic time, as there are quadratically many
+// runs to report.
+fn find_weaknesses<T: Number + Into<BigInt>>(
+    numbers: &[T],
+    target: &T,
+) -> Result<Vec<Weakness<T>>, Box<dyn Error>> {
+    let mut prefix = Vec::with_capacity(numbers.len() + 1);
+    prefix.push(T::zero());
+    for n in numbers {
+        match prefix.last().unwrap().checked_add(n) {
+            Some(sum) => prefix.push(sum),
+            None => break,
+        }
+    }
+
+    let ranges = if prefix.len() == numbers.len() + 1 {
+        matching_ranges(&prefix, target)
+    } else {
+        let mut prefix = vec![BigInt::zero()];
+        for n in numbers {
+            let sum = prefix.last().unwrap() + n.clone().into();
+            prefix.push(sum);
+        }
+        matching_ranges(&prefix, &target.clone().into())
+    };
+
+    let mut weaknesses = Vec::with_capacity(ranges.len());
+    // The start being scanned, how far, and the extremes so far.
+    let mut scan: Option<(usize, usize, T, T)> = None;
+    for range in ranges {
+        let (_, scanned, min, max) = match &mut scan {
+            Some(s) if s.0 == range.start => s,
+            _ => {
+                let first = numbers[range.start].clone();
+                scan.insert((range.start, range.start + 1, first.clone(), first))
+            }
+        };
+        for n in &numbers[*scanned..range.end] {
+            if n < min {
+                *min = n.clone();
+            }
+            if n > max {
+                *max = n.clone();
+            }
+        }
+        *scanned = range.end;
+
+        let weakness = min.checked_add(max).ok_or(format!(
+            "Weakness of {}..{} overflows",
+            range.start, range.end
+        ))?;
+        weaknesses.push(Weakness {
+            range,
+            min: min.clone(),
+            max: max.clone(),
+            weakness,
+        });
+    }
+    Ok(weaknesses)
+}
 
-    let numbers = file
-        .lines()
-        .map(|x| x.unwrap().parse().unwrap())
-        .collect::<Vec<u64>>();
+fn read_numbers<T>(file_name: &str) -> Result<Vec<T>, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let file = BufReader::new(File::open(file_name)?);
 
-    let (_, first_invalid) = numbers
-        .iter()
-        .skip(WINDOW_SIZE)
+    file.lines()
         .enumerate()
-        .filter(|(i, d)| {
-            (&numbers[*i..*i + WINDOW_SIZE])
-                .iter()
-                .tuple_combinations()
-                .map(|(a, b)| a + b)
-                .position(|q| q == **d)
-                .is_none()
+        .map(|(i, line)| {
+            let line = line?;
+            line.trim()
+                .parse()
+                .map_err(|e| format!("line {}: cannot parse {:?}: {}", i + 1, line, e).into())
         })
-        .next()
-        .ok_or("No invalid number found")?;
+        .collect()
+}
+
+fn solve<T>(file_name: &str, window_size: usize) -> Result<(), Box<dyn Error>>
+where
+    T: Number + Into<BigInt> + FromStr,
+    T::Err: Display,
+{
+    let numbers = read_numbers::<T>(file_name)?;
 
+    let invalid = XmasValidator::new(window_size)
+        .invalid(numbers.iter().cloned())
+        .collect::<Vec<(usize, T)>>();
+    for (i, n) in &invalid {
+        println!("Invalid number {} at index {}", n, i);
+    }
+
+    let (_, first_invalid) = invalid.first().ok_or("No invalid number found")?;
     println!("First invalid number is {}", first_invalid);
 
-    for start_pos in 0..numbers.len() {
-        for length in 2..numbers.len() - start_pos {
-            let contiguous_range = &numbers[start_pos..start_pos + length];
-            if contiguous_range.iter().sum::<u64>() == *first_invalid {
-                println!(
-                    "Encryption weakness is {}",
-                    contiguous_range.iter().min().unwrap() + contiguous_range.iter().max().unwrap()
-                );
-                return Ok(());
-            }
-        }
+    for w in find_weaknesses(&numbers, first_invalid)? {
+        println!(
+            "Encryption weakness is {} (indices {}..{}, min {}, max {})",
+            w.weakness, w.range.start, w.range.end, w.min, w.max
+        );
     }
 
     Ok(())
 }
+
+// Usage: day9 [WINDOW] [u64|i64|i128|big]
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = env::args().collect::<Vec<String>>();
+    let window_size = match args.get(1) {
+        Some(arg) => arg.parse()?,
+        None => 25,
+    };
+
+    match args.get(2).map_or("u64", |a| a.as_str()) {
+        "u64" => solve::<u64>("input.txt", window_size),
+        "i64" => solve::<i64>("input.txt", window_size),
+        "i128" => solve::<i128>("input.txt", window_size),
+        "big" => solve::<BigInt>("input.txt", window_size),
+        other => Err(format!("Unknown number type {}", other).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: [u64; 20] = [
+        35, 20, 15, 25, 47, 40, 62, 55, 65, 95, 102, 117, 150, 182, 127, 219, 299, 277, 309, 576,
+    ];
+
+    fn invalid<T: Number>(preamble: usize, numbers: &[T]) -> Vec<(usize, T)> {
+        XmasValidator::new(preamble)
+            .invalid(numbers.iter().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn test_validator() {
+        assert_eq!(vec![(14, 127)], invalid(5, &EXAMPLE));
+
+        // Only the two numbers just before each one can pair up.
+        assert_eq!(
+            vec![(3, 3), (4, 5), (5, 10)],
+            invalid(2, &[1u64, 2, 3, 3, 5, 10, 15])
+        );
+    }
+
+    #[test]
+    fn test_validator_preamble_0() {
+        // With no window, nothing can be summed, so every number is invalid.
+        let mut validator = XmasValidator::new(0);
+        assert!(!validator.push(1u64));
+        assert!(!validator.push(2u64));
+        assert_eq!(vec![(0, 1), (1, 2)], invalid(0, &[1u64, 2]));
+    }
+
+    #[test]
+    fn test_validator_overflow() {
+        // MAX + 1 overflows and is left out, but the other pairs still count.
+        let mut validator = XmasValidator::new(3);
+        assert!(validator.push(u64::MAX));
+        assert!(validator.push(1u64));
+        assert!(validator.push(2u64));
+        assert!(validator.push(3u64));
+        assert!(!validator.push(0u64));
+
+        // The overflowing pairs slid out with MAX without upsetting the sums.
+        assert!(validator.push(5u64));
+        assert!(validator.push(8u64));
+    }
+
+    fn ranges<T: Number + Into<BigInt>>(numbers: &[T], target: T) -> Vec<Range<usize>> {
+        find_weaknesses(numbers, &target)
+            .unwrap()
+            .into_iter()
+            .map(|w| w.range)
+            .collect()
+    }
+
+    #[test]
+    fn test_find_weaknesses() {
+        assert_eq!(
+            vec![Weakness {
+                range: 2..6,
+                min: 15,
+                max: 47,
+                weakness: 62
+            }],
+            find_weaknesses(&EXAMPLE, &127).unwrap()
+        );
+
+        // Overlapping and nested runs are all found.
+        let weaknesses = find_weaknesses(&[1u64, 2, 3, 3, 2, 1, 6], &6).unwrap();
+        assert_eq!(
+            vec![0..3, 2..4, 3..6],
+            weaknesses
+                .iter()
+                .map(|w| w.range.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![4, 6, 4],
+            weaknesses.iter().map(|w| w.weakness).collect::<Vec<_>>()
+        );
+
+        // A single number is not a run.
+        assert_eq!(Vec::<Range<usize>>::new(), ranges(&[6u64, 1], 6));
+    }
+
+    #[test]
+    fn test_find_weaknesses_zeros_and_negatives() {
+        // Every run of two or more zeros sums to zero.
+        assert_eq!(
+            vec![0..2, 0..3, 0..4, 1..3, 1..4, 2..4],
+            ranges(&[0u64; 4], 0)
+        );
+        assert_eq!(vec![0..2, 0..3, 1..3], ranges(&[0u64, 5, 0, 1], 5));
+
+        assert_eq!(
+            vec![Weakness {
+                range: 0..4,
+                min: -3,
+                max: 4,
+                weakness: 1
+            }],
+            find_weaknesses(&[4i64, -3, 2, -1, 5], &2).unwrap()
+        );
+        assert_eq!(vec![0..2, 1..3], ranges(&[-2i64, 2, -2], 0));
+    }
+
+    #[test]
+    fn test_negative_numbers() {
+        let numbers = [-5i64, 3, -2, 1, -4, -1, 7];
+
+        assert_eq!(vec![(4, -4), (6, 7)], invalid(3, &numbers));
+        assert_eq!(vec![0..7, 2..4], ranges(&numbers, -1));
+        assert_eq!(vec![0..2, 1..5], ranges(&numbers, -2));
+    }
+
+    #[test]
+    fn test_near_max() {
+        let max = u64::MAX;
+        let numbers = [max - 1, 1, max, 2, max, 1, 1];
+
+        // Only (max - 1) + 1 fits; every other pair with a large number
+        // overflows and is skipped.
+        assert_eq!(vec![(3, 2), (4, max), (5, 1), (6, 1)], invalid(2, &numbers));
+        // The running total overflows at the third number, so the search
+        // falls back to `BigInt`.
+        assert_eq!(vec![0..2], ranges(&numbers, max));
+        assert_eq!(vec![5..7], ranges(&numbers, 2));
+    }
+
+    #[test]
+    fn test_big_int() {
+        let big = |n: &str| n.parse::<BigInt>().unwrap();
+        let numbers = [
+            big("100000000000000000000000000000"),
+            big("-1"),
+            big("99999999999999999999999999999"),
+            big("1"),
+        ];
+
+        assert_eq!(vec![(3, big("1"))], invalid(2, &numbers));
+        let weaknesses = find_weaknesses(&numbers, &big("199999999999999999999999999998")).unwrap();
+        assert_eq!(
+            vec![0..3],
+            weaknesses
+                .iter()
+                .map(|w| w.range.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(big("99999999999999999999999999999"), weaknesses[0].weakness);
+    }
+
+    #[test]
+    fn test_read_numbers() {
+        let path = env::temp_dir().join(format!("day9_test_{}.txt", std::process::id()));
+        std::fs::write(&path, "1\n-2\n 3 \nfour\n").unwrap();
+        let file_name = path.to_str().unwrap();
+
+        assert_eq!(
+            "line 2: cannot parse \"-2\": invalid digit found in string",
+            read_numbers::<u64>(file_name).unwrap_err().to_string()
+        );
+        assert_eq!(
+            "line 4: cannot parse \"four\": invalid digit found in string",
+            read_numbers::<i64>(file_name).unwrap_err().to_string()
+        );
+        std::fs::write(&path, "1\n-2\n 3 \n").unwrap();
+        assert_eq!(vec![1, -2, 3], read_numbers::<i64>(file_name).unwrap());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}